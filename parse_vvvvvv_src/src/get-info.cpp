@@ -9,4 +9,5 @@ int main() {
         offsetof(Game, roomx), offsetof(Game, roomy),
         offsetof(Game, state), offsetof(Game, gamestate),
         offsetof(Game, frames));
+    printf("%zu\n%zu\n", offsetof(Game, deathcounts), offsetof(Game, savetime));
 }
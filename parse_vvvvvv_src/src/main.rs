@@ -2,10 +2,15 @@
 we need to figure out:
  1. which enum values in src/Enums.h correspond to GAMEMODE, MAPMODE, TELEPORTERMODE, GAMECOMPLETE, GAMECOMPLETE2
  2. what are the struct offsets of Game::{roomx, roomy, state, gamestate, frames} in src/Game.h
+ 3. the offset of Game::deathcounts
+ 4. the offset of Game::savetime, which vitellary finds the game object from when the executable is
+    stripped. that depends on the C++ standard library, and vitellary wants it for libstdc++, so
+    run this on Linux.
 */
 
 use anyhow::{anyhow, Result};
 use git2::Repository;
+use serde_json::json;
 use std::{
     collections::HashMap,
     env, fs,
@@ -88,7 +93,7 @@ impl Revision {
             playing_states.push(next_u32(&mut lines)?);
         }
         let game_size = next_u32(&mut lines)?;
-        let fields = ["room_x", "room_y", "state", "gamestate", "timer"];
+        let fields = ["room_x", "room_y", "state", "gamestate", "timer", "deaths", "savetime"];
         let mut offsets = HashMap::new();
         for field in fields {
             offsets.insert(field, next_u32(&mut lines)?);
@@ -103,11 +108,54 @@ impl Revision {
             offsets,
         })
     }
+
+    // the layout as it appears in revisions.json
+    fn to_json(&self) -> serde_json::Value {
+        json!({
+            "game_object_size": self.game_size,
+            "playing_states": self.playing_states,
+            "offsets": self.offsets,
+        })
+    }
+
+    // the `Revision { .. }` expression for this revision's layout in vitellary's revisions.rs
+    fn to_rust(&self) -> String {
+        let mut rust = format!(
+            "Revision {{ game_object_size: {}, playing_states: [{}],",
+            self.game_size,
+            self.playing_states
+                .iter()
+                .map(|x| x.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        // sort keys for consistent output
+        let mut fields = self.offsets.keys().collect::<Vec<_>>();
+        fields.sort();
+        for field in fields {
+            let offset = self.offsets[field];
+            if OPTIONAL_FIELDS.contains(field) {
+                rust.push_str(&format!(" {field}_offset: Some({offset}),"));
+            } else {
+                rust.push_str(&format!(" {field}_offset: {offset},"));
+            }
+        }
+        rust.push_str(" }");
+        rust
+    }
 }
 
+// fields whose offsets are `Option`s in vitellary's revisions.rs, because they were added to it
+// before it could be regenerated
+const OPTIONAL_FIELDS: [&str; 2] = ["deaths", "savetime"];
+
+// the version of the revisions.json format. vitellary refuses to load files with a different
+// version, so change this whenever the format changes incompatibly.
+const REVISIONS_FILE_VERSION: u32 = 3;
+
 // if we ever need to invalidate the cache (e.g. add more struct fields),
 // we can change this string
-const CACHE_IDENTIFIER: &str = "CACHE VERSION 2\n";
+const CACHE_IDENTIFIER: &str = "CACHE VERSION 4\n";
 
 fn main() -> Result<()> {
     let src_dir = env::args().nth(1).ok_or_else(|| {
@@ -187,9 +235,37 @@ fn main() -> Result<()> {
         .iter()
         .map(|(id, contents)| Revision::from_cache_contents(id.to_string(), contents))
         .collect();
-    let mut revisions = revisions?;
+    let revisions = revisions?;
 
-    // this part here adds revisions for tagged commits and for the master branch's latest commit (refs/heads/master)
+    // most commits don't change the layout of `Game`, so we only write out each distinct layout
+    // once and have commits refer to it by index.
+    let mut layouts: Vec<String> = vec![];
+    let mut layouts_json = vec![];
+    let mut commits = vec![];
+    for (order, revision) in revisions.iter().enumerate() {
+        let layout = revision.to_rust();
+        let index = match layouts.iter().position(|l| *l == layout) {
+            Some(i) => i,
+            None => {
+                layouts.push(layout);
+                layouts_json.push(revision.to_json());
+                layouts.len() - 1
+            }
+        };
+        commits.push((revision.commit_id.clone(), index, order));
+    }
+    // vitellary can load this at runtime (with --revisions-file), so people can use it with newer
+    // commits without recompiling. commits are in history order, newest first.
+    let commits_json: Vec<_> = commits
+        .iter()
+        .map(|(id, layout, _)| json!([id, layout]))
+        .collect();
+    assert!(layouts.len() <= 256, "too many layouts for a u8 index");
+    // sorted so that we can binary search for (abbreviated) commit IDs
+    commits.sort();
+
+    // this part here adds names for tagged commits and for the master branch's latest commit (refs/heads/master)
+    let mut tags = vec![];
     for reference in repo.references()? {
         let reference = reference?;
         if let (Some(target), Some(name)) = (reference.target(), reference.name()) {
@@ -199,52 +275,63 @@ fn main() -> Result<()> {
                         let slash = name.rfind("/").unwrap();
                         let name = &name[slash + 1..];
                         let commit = commit.id().to_string();
-                        if let Some(revision) = revisions.iter().find(|r| r.commit_id == commit) {
-                            let mut revision = revision.clone();
-                            revision.commit_id = name.to_string();
-                            revisions.push(revision);
+                        if let Some((_, layout, _)) = commits.iter().find(|c| c.0 == commit) {
+                            tags.push((name.to_string(), *layout));
                         }
                     }
                 }
             }
         }
     }
+    tags.sort();
+
+    let json_path = "revisions.json";
+    let json = json!({
+        "version": REVISIONS_FILE_VERSION,
+        "layouts": layouts_json,
+        "tags": tags.iter().cloned().collect::<HashMap<_, _>>(),
+        "commits": commits_json,
+    });
+    fs::write(json_path, serde_json::to_string_pretty(&json)?)?;
 
     let output_path = "../src/game/revisions.rs";
     let mut output = io::BufWriter::new(fs::File::create(output_path)?);
     writeln!(
         output,
         "// this file was auto-generated by parse_vvvvvv_src
-use std::collections::HashMap;
-use crate::Revision;\n"
+use crate::game::{{Commit, Revision}};\n"
+    )?;
+    writeln!(
+        output,
+        "pub(super) static LAYOUTS: [Revision; {}] = [",
+        layouts.len()
     )?;
+    for layout in &layouts {
+        writeln!(output, "{layout},")?;
+    }
+    writeln!(output, "];")?;
     writeln!(
         output,
-        "pub(crate) fn get() -> HashMap<&'static str, Revision> {{ HashMap::from(["
+        "pub(super) static TAGS: [(&str, u8); {}] = [",
+        tags.len()
     )?;
-    for version in revisions {
-        write!(output, "(\"{}\", Revision {{", version.commit_id)?;
-        write!(output, "game_object_size: {},", version.game_size)?;
-        // note: Debug output is not stable, so technically rust could change the Vec Debug implementation in the future.
-        write!(
+    for (name, layout) in &tags {
+        writeln!(output, "(\"{name}\", {layout}),")?;
+    }
+    writeln!(output, "];")?;
+    // one line per commit is much easier to read (and diff) than what rustfmt would do
+    writeln!(
+        output,
+        "#[rustfmt::skip]\npub(super) static COMMITS: [Commit; {}] = [",
+        commits.len()
+    )?;
+    for (id, layout, order) in &commits {
+        writeln!(
             output,
-            "playing_states: vec![{}],",
-            version
-                .playing_states
-                .iter()
-                .map(|x| x.to_string())
-                .collect::<Vec<_>>()
-                .join(", ")
+            "    Commit {{ id: \"{id}\", layout: {layout}, order: {order} }},"
         )?;
-        // sort keys for consistent output
-        let mut fields = version.offsets.keys().collect::<Vec<_>>();
-        fields.sort();
-        for field in fields {
-            write!(output, "{field}_offset: {},", version.offsets[field])?;
-        }
-        writeln!(output, "}}),")?;
     }
-    write!(output, "])}}")?;
+    writeln!(output, "];")?;
     drop(output); // make sure file is sync'd
 
     let fmt_status = Command::new("rustfmt").arg(output_path).status()?;
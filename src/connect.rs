@@ -0,0 +1,93 @@
+//! Connecting out to LiveSplit's server component, rather than waiting for LiveSplit One to connect
+//! to us.
+
+#![allow(clippy::doc_markdown)]
+
+use crate::connection::{Connection, Lines, Timer};
+use crate::hub::Hub;
+use crate::protocol::Protocol;
+use crate::sink::{self, Source};
+use anyhow::{anyhow, Result};
+use std::fmt;
+use std::net::TcpStream;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// how long to wait before trying to connect again
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Where LiveSplit's server component is listening: `tcp://HOST:PORT` or `ws://HOST:PORT/PATH`.
+#[derive(Debug, Clone)]
+pub(crate) enum Endpoint {
+    Tcp(String),
+    WebSocket(String),
+}
+
+impl FromStr for Endpoint {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(address) = s.strip_prefix("tcp://") {
+            Ok(Endpoint::Tcp(address.trim_end_matches('/').into()))
+        } else if s.starts_with("ws://") {
+            Ok(Endpoint::WebSocket(s.into()))
+        } else {
+            Err(format!(
+                "expected tcp://HOST:PORT or ws://HOST:PORT/PATH, got {s:?}"
+            ))
+        }
+    }
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Endpoint::Tcp(address) => write!(f, "tcp://{}", address),
+            Endpoint::WebSocket(url) => f.write_str(url),
+        }
+    }
+}
+
+impl Endpoint {
+    fn connect(&self) -> Result<Box<dyn Connection + Send>> {
+        match self {
+            Endpoint::Tcp(address) => Ok(Box::new(Lines::new(TcpStream::connect(address)?))),
+            Endpoint::WebSocket(url) => {
+                let uri = url.parse::<tungstenite::http::Uri>()?;
+                let host = uri.host().ok_or_else(|| anyhow!("no host in {}", url))?;
+                let stream = TcpStream::connect((host, uri.port_u16().unwrap_or(80)))?;
+                let (websocket, _) = tungstenite::client(url.as_str(), stream)
+                    .map_err(|e| anyhow!("handshake failed: {}", e))?;
+                Ok(Box::new(websocket))
+            }
+        }
+    }
+}
+
+impl Source for Endpoint {
+    /// Keep connecting to the timer and sending it updates, for as long as vitellary runs.
+    fn run(self: Box<Self>, hub: &Arc<Hub>) {
+        let mut warned = false;
+        loop {
+            match self.connect() {
+                Ok(connection) => {
+                    log::info!("connected to {}", self);
+                    warned = false;
+                    let updates = hub.subscribe();
+                    let mut timer =
+                        Timer::new(self.to_string(), connection, Protocol::LiveSplit, &updates);
+                    if let Err(e) = sink::run(&mut timer, &updates) {
+                        log::warn!("disconnected from {}: {}", self, e);
+                    }
+                }
+                Err(e) if !warned => {
+                    log::warn!("failed to connect to {}: {:#}; retrying", self, e);
+                    warned = true;
+                }
+                Err(e) => log::debug!("failed to connect to {}: {:#}", self, e),
+            }
+            std::thread::sleep(RECONNECT_INTERVAL);
+        }
+    }
+}
@@ -0,0 +1,154 @@
+//! Talking to a timer over a connection, whichever end opened it.
+
+use crate::game::Update;
+use crate::hub::Subscription;
+use crate::protocol::{Protocol, Session};
+use crate::server::Stream;
+use crate::sink::Sink;
+use anyhow::Result;
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+use tungstenite::{Message, WebSocket};
+
+/// how long to wait for an update or a message before checking for the other
+pub(crate) const POLL_INTERVAL: Duration = Duration::from_millis(10);
+/// how long to wait for another message once we've had one, to catch up on everything that's
+/// arrived
+const CATCH_UP_TIMEOUT: Duration = Duration::from_millis(1);
+
+/// A connection that messages can be sent and received over.
+pub(crate) trait Connection {
+    /// Wait up to `timeout` for a message. `Ok(None)` means there wasn't one.
+    fn receive(&mut self, timeout: Duration) -> Result<Option<String>>;
+    fn send(&mut self, message: String) -> Result<()>;
+}
+
+fn is_timeout(e: &std::io::Error) -> bool {
+    matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut)
+}
+
+impl<S: Stream> Connection for WebSocket<S> {
+    fn receive(&mut self, timeout: Duration) -> Result<Option<String>> {
+        self.get_ref().set_read_timeout(Some(timeout))?;
+        match self.read_message() {
+            Ok(Message::Text(message)) => Ok(Some(message)),
+            Ok(_) => Ok(None),
+            Err(tungstenite::Error::Io(e)) if is_timeout(&e) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn send(&mut self, message: String) -> Result<()> {
+        Ok(self.write_message(Message::Text(message))?)
+    }
+}
+
+#[allow(clippy::doc_markdown)]
+/// A TCP connection with a message on each line, like LiveSplit's server component uses.
+pub(crate) struct Lines {
+    reader: BufReader<TcpStream>,
+    /// the part of a line we've received so far
+    line: Vec<u8>,
+}
+
+impl Lines {
+    pub(crate) fn new(stream: TcpStream) -> Self {
+        Self {
+            reader: BufReader::new(stream),
+            line: vec![],
+        }
+    }
+}
+
+impl Connection for Lines {
+    fn receive(&mut self, timeout: Duration) -> Result<Option<String>> {
+        self.reader.get_ref().set_read_timeout(Some(timeout))?;
+        // on a timeout, whatever was read is left in `self.line` for next time
+        match self.reader.read_until(b'\n', &mut self.line) {
+            Ok(0) => Err(std::io::Error::from(ErrorKind::UnexpectedEof).into()),
+            Ok(_) if self.line.ends_with(b"\n") => {
+                let line = String::from_utf8_lossy(&self.line).trim_end().to_owned();
+                self.line.clear();
+                Ok(Some(line))
+            }
+            Ok(_) => Ok(None),
+            Err(e) if is_timeout(&e) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn send(&mut self, message: String) -> Result<()> {
+        let stream = self.reader.get_mut();
+        stream.write_all(message.as_bytes())?;
+        stream.write_all(b"\r\n")?;
+        Ok(())
+    }
+}
+
+/// A timer at the other end of a connection, kept up to date until the connection fails.
+pub(crate) struct Timer {
+    /// who's on the other end, for logging
+    peer: String,
+    connection: Box<dyn Connection + Send>,
+    session: Session,
+}
+
+impl Timer {
+    /// `updates` is the subscription the timer will be fed with, which knows what it missed.
+    pub(crate) fn new(
+        peer: String,
+        connection: Box<dyn Connection + Send>,
+        protocol: Protocol,
+        updates: &Subscription,
+    ) -> Self {
+        Self {
+            peer,
+            connection,
+            session: Session::new(protocol, updates.missed_splits),
+        }
+    }
+
+    /// Handle every message that's arrived, waiting up to `timeout` for the first, so that we act
+    /// on what the timer is doing now. Then ask what it's doing, if it's time to.
+    fn receive(&mut self, timeout: Duration) -> Result<()> {
+        let mut timeout = timeout;
+        while let Some(message) = self.connection.receive(timeout)? {
+            self.session.receive(&message);
+            timeout = CATCH_UP_TIMEOUT;
+        }
+        let queries = self.session.queries();
+        self.send(queries)
+    }
+
+    fn send(&mut self, messages: Vec<String>) -> Result<()> {
+        for message in messages {
+            self.connection.send(message)?;
+        }
+        Ok(())
+    }
+}
+
+impl Sink for Timer {
+    fn name(&self) -> String {
+        format!("timer {}", self.peer)
+    }
+
+    fn update(&mut self, update: &Update) -> Result<()> {
+        self.receive(CATCH_UP_TIMEOUT)?;
+        let messages = self.session.encode(update);
+        self.send(messages)
+    }
+
+    fn idle(&mut self) -> Result<()> {
+        self.receive(POLL_INTERVAL)
+    }
+
+    fn idle_interval(&self) -> Duration {
+        POLL_INTERVAL
+    }
+
+    fn is_ready(&self) -> bool {
+        !self.session.is_syncing()
+    }
+}
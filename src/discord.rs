@@ -0,0 +1,326 @@
+//! Showing the run in Discord (Rich Presence), over the Discord client's local IPC socket.
+//!
+//! Each message on the socket is an opcode and a length (both `u32`, little-endian) followed by
+//! that much JSON. We say hello with a handshake, then send `SET_ACTIVITY` commands.
+
+use crate::game::{describe_room, format_time, Event, Update};
+use crate::reconnect::{Reconnect, Status};
+use crate::sink::Sink;
+use anyhow::{anyhow, bail, Result};
+use serde_json::{json, Value};
+use std::env;
+use std::io::{self, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// opcodes
+const HANDSHAKE: u32 = 0;
+const FRAME: u32 = 1;
+const CLOSE: u32 = 2;
+const PING: u32 = 3;
+const PONG: u32 = 4;
+
+/// how long to wait for Discord to answer
+const TIMEOUT: Duration = Duration::from_secs(5);
+/// how long to wait before looking for Discord again
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(10);
+/// the least time between activity updates; Discord allows 5 every 20 seconds
+const UPDATE_INTERVAL: Duration = Duration::from_secs(4);
+
+/// The sockets the Discord client might be listening on.
+fn socket_paths() -> Vec<PathBuf> {
+    let dir = ["XDG_RUNTIME_DIR", "TMPDIR", "TMP", "TEMP"]
+        .into_iter()
+        .find_map(env::var_os)
+        .map_or_else(|| PathBuf::from("/tmp"), PathBuf::from);
+    (0..10)
+        .map(|i| dir.join(format!("discord-ipc-{}", i)))
+        .collect()
+}
+
+fn write_message(stream: &mut UnixStream, op: u32, payload: &Value) -> io::Result<()> {
+    let payload = payload.to_string();
+    let len = u32::try_from(payload.len()).expect("payload too long");
+    let mut message = Vec::with_capacity(8 + payload.len());
+    message.extend_from_slice(&op.to_le_bytes());
+    message.extend_from_slice(&len.to_le_bytes());
+    message.extend_from_slice(payload.as_bytes());
+    stream.write_all(&message)
+}
+
+fn read_message(stream: &mut UnixStream) -> Result<(u32, Value)> {
+    let mut header = [0; 8];
+    stream.read_exact(&mut header)?;
+    let op = u32::from_le_bytes(header[..4].try_into().unwrap());
+    let len = u32::from_le_bytes(header[4..].try_into().unwrap());
+    let mut payload = vec![0; len as usize];
+    stream.read_exact(&mut payload)?;
+    Ok((op, serde_json::from_slice(&payload)?))
+}
+
+/// Wait for the response with `nonce` (or, without one, for the next command), answering pings.
+fn receive(stream: &mut UnixStream, nonce: Option<&str>) -> Result<Value> {
+    loop {
+        match read_message(stream)? {
+            (FRAME, message) if nonce.is_none_or(|nonce| message["nonce"] == nonce) => {
+                return Ok(message);
+            }
+            (PING, payload) => write_message(stream, PONG, &payload)?,
+            (CLOSE, payload) => bail!(
+                "Discord closed the connection: {}",
+                payload["message"].as_str().unwrap_or("no reason given")
+            ),
+            _ => {}
+        }
+    }
+}
+
+/// Connect to the first of `sockets` that the Discord client is listening on, and say hello.
+fn connect(sockets: &[PathBuf], client_id: &str) -> Result<UnixStream> {
+    let mut stream = sockets
+        .iter()
+        .find_map(|path| UnixStream::connect(path).ok())
+        .ok_or_else(|| anyhow!("Discord isn't running"))?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    let hello = json!({ "v": 1, "client_id": client_id });
+    write_message(&mut stream, HANDSHAKE, &hello)?;
+    receive(&mut stream, None)?;
+    Ok(stream)
+}
+
+pub(crate) struct Discord {
+    /// the ID of the Discord application to show as
+    client_id: String,
+    /// where the Discord client might be listening
+    sockets: Vec<PathBuf>,
+    link: Reconnect<UnixStream>,
+    next_nonce: u64,
+    /// the activity we last sent, and when
+    sent: Option<(Value, Instant)>,
+
+    /// when the run in progress started, in seconds since the Unix epoch
+    run_started: Option<u64>,
+    /// the last split in the run in progress, and the game time it happened at
+    last_split: Option<(Event, Duration)>,
+    /// the game time the run was finished in, until the next one starts
+    finished: Option<Duration>,
+    attached: bool,
+    latest: Option<Update>,
+}
+
+impl Discord {
+    pub(crate) fn new(client_id: String) -> Self {
+        Self {
+            client_id,
+            sockets: socket_paths(),
+            link: Reconnect::new("Discord".into(), RECONNECT_INTERVAL),
+            next_nonce: 0,
+            sent: None,
+            run_started: None,
+            last_split: None,
+            finished: None,
+            attached: true,
+            latest: None,
+        }
+    }
+
+    /// The activity to show for the latest update, or null to show nothing.
+    fn activity(&self, update: &Update) -> Value {
+        if !self.attached {
+            return Value::Null;
+        }
+        let deaths = update.state.deaths.map(|deaths| match deaths {
+            1 => "1 death".into(),
+            deaths => format!("{} deaths", deaths),
+        });
+        if let Some(time) = self.finished {
+            let mut activity = json!({ "details": format!("Finished in {}", format_time(time)) });
+            if let Some(deaths) = deaths {
+                activity["state"] = deaths.into();
+            }
+            return activity;
+        }
+        let Some(started) = self.run_started else {
+            return json!({ "details": "Not in a run" });
+        };
+        let details = match self.last_split {
+            Some((split, time)) => format!("{} at {}", split, format_time(time)),
+            None => "Just started".into(),
+        };
+        let room = describe_room(update.state.room);
+        json!({
+            "details": details,
+            "state": match deaths {
+                Some(deaths) => format!("{} · {}", room, deaths),
+                None => room,
+            },
+            "timestamps": { "start": started },
+        })
+    }
+
+    fn set_activity(&mut self, activity: &Value) -> Result<()> {
+        let Some(stream) = self.link.get() else {
+            return Ok(());
+        };
+        self.next_nonce += 1;
+        let nonce = self.next_nonce.to_string();
+        let command = json!({
+            "cmd": "SET_ACTIVITY",
+            "args": { "pid": std::process::id(), "activity": activity },
+            "nonce": nonce,
+        });
+        write_message(stream, FRAME, &command)?;
+        let response = receive(stream, Some(&nonce))?;
+        if response["evt"] == "ERROR" {
+            self.link
+                .refused("SET_ACTIVITY", response["data"]["message"].as_str());
+        }
+        Ok(())
+    }
+
+    /// Show the activity for the latest update, if it's changed and Discord would let us.
+    fn refresh(&mut self) {
+        let Some(update) = self.latest else {
+            return;
+        };
+        match self.link.ensure(|| connect(&self.sockets, &self.client_id)) {
+            Status::Disconnected => return,
+            // whatever was showing went away with the last connection
+            Status::Reconnected => self.sent = None,
+            Status::Connected => {}
+        }
+        let activity = self.activity(&update);
+        let due = match &self.sent {
+            Some((sent, _)) if *sent == activity => false,
+            Some((_, at)) => at.elapsed() >= UPDATE_INTERVAL,
+            None => true,
+        };
+        if due {
+            if let Err(e) = self.set_activity(&activity) {
+                self.link.lost(&e, false);
+                return;
+            }
+            self.sent = Some((activity, Instant::now()));
+        }
+    }
+}
+
+impl Sink for Discord {
+    fn name(&self) -> String {
+        "Discord".into()
+    }
+
+    fn update(&mut self, update: &Update) -> Result<()> {
+        match update.event {
+            Some(Event::NewGame) => {
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default();
+                self.run_started = Some(now.as_secs());
+                self.last_split = None;
+                self.finished = None;
+            }
+            Some(Event::Reset) => {
+                self.run_started = None;
+                self.last_split = None;
+            }
+            Some(Event::GameComplete) => {
+                self.run_started = None;
+                self.finished = Some(update.time);
+            }
+            Some(Event::Detached) => self.attached = false,
+            Some(Event::Attached) => self.attached = true,
+            Some(split) => self.last_split = Some((split, update.time)),
+            None => {}
+        }
+        self.latest = Some(*update);
+        self.refresh();
+        Ok(())
+    }
+
+    fn idle(&mut self) -> Result<()> {
+        self.refresh();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::os::unix::net::UnixListener;
+    use std::sync::mpsc;
+    use std::thread;
+
+    /// A stand-in for the Discord client, which sends back each message it gets (after checking
+    /// that pings are answered), and refuses the second activity.
+    fn fake_discord(listener: &UnixListener, messages: &mpsc::Sender<(u32, Value)>) {
+        let (mut stream, _) = listener.accept().unwrap();
+        let handshake = read_message(&mut stream).unwrap();
+        messages.send(handshake).unwrap();
+        let ready = json!({ "cmd": "DISPATCH", "evt": "READY", "data": {} });
+        write_message(&mut stream, FRAME, &ready).unwrap();
+        for i in 0.. {
+            let Ok((op, command)) = read_message(&mut stream) else {
+                return;
+            };
+            write_message(&mut stream, PING, &json!({ "i": i })).unwrap();
+            assert_eq!(
+                read_message(&mut stream).unwrap(),
+                (PONG, json!({ "i": i }))
+            );
+            let response = if i == 1 {
+                json!({ "evt": "ERROR", "data": { "message": "no" }, "nonce": command["nonce"] })
+            } else {
+                json!({ "evt": null, "data": {}, "nonce": command["nonce"] })
+            };
+            write_message(&mut stream, FRAME, &response).unwrap();
+            messages.send((op, command)).unwrap();
+        }
+    }
+
+    #[test]
+    fn shows_the_run() {
+        let dir = env::temp_dir().join(format!("vitellary-discord-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let socket = dir.join("discord-ipc-0");
+        let _ = fs::remove_file(&socket);
+        let listener = UnixListener::bind(&socket).unwrap();
+        let (sender, messages) = mpsc::channel();
+        thread::spawn(move || fake_discord(&listener, &sender));
+        let next = || messages.recv_timeout(TIMEOUT).unwrap();
+
+        let mut discord = Discord::new("1234".into());
+        discord.sockets = vec![dir.join("discord-ipc-1"), socket.clone()];
+        let start = Update::test(Duration::ZERO, (115, 100), 0, Some(Event::NewGame));
+        discord.update(&start).unwrap();
+        assert_eq!(next(), (HANDSHAKE, json!({ "v": 1, "client_id": "1234" })));
+        let (op, command) = next();
+        assert_eq!(op, FRAME);
+        assert_eq!(command["cmd"], "SET_ACTIVITY");
+        let activity = &command["args"]["activity"];
+        assert_eq!(activity["details"], "Just started");
+        assert_eq!(activity["state"], "Warp Zone · 0 deaths");
+        assert!(activity["timestamps"]["start"].is_u64());
+
+        // Discord only takes so many updates, so this one waits
+        let split = Update::test(Duration::from_millis(83_500), (120, 100), 2, None);
+        let split = Update {
+            event: Some(Event::Verdigris),
+            ..split
+        };
+        discord.update(&split).unwrap();
+        assert!(messages.try_recv().is_err());
+        discord.sent.as_mut().unwrap().1 -= UPDATE_INTERVAL;
+        discord.idle().unwrap();
+        let (_, command) = next();
+        let activity = &command["args"]["activity"];
+        assert_eq!(activity["details"], "Verdigris at 1:23.50");
+        assert_eq!(activity["state"], "Outside Dimension VVVVVV · 2 deaths");
+        // refusing an activity isn't a reason to disconnect
+        assert!(discord.link.get().is_some());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
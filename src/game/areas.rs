@@ -0,0 +1,39 @@
+//! Which area of VVVVVV a room is in, for showing people where the player is.
+//!
+//! This follows `mapclass::area` and `mapclass::currentarea` in VVVVVV's `Map.cpp`, for the areas
+//! of the main map whose rooms we're sure of. We don't read room names from the game, so rooms
+//! anywhere else are shown by their coordinates. Custom levels use the same coordinates as the
+//! main map, so their rooms get its area names too.
+
+use std::ops::RangeInclusive;
+
+/// Areas of the main map, whose rooms are numbered from 100 to 119 on each axis, as ranges of room
+/// coordinates.
+const AREAS: [(&str, RangeInclusive<u32>, RangeInclusive<u32>); 3] = [
+    ("The Tower", 109..=109, 100..=119),
+    ("Laboratory", 101..=107, 100..=101),
+    ("Warp Zone", 113..=119, 100..=109),
+];
+
+/// The name of the area `room` is in, if we know it.
+fn area((x, y): (u32, u32)) -> Option<&'static str> {
+    let main_map = 100..120;
+    if (x, y) == (0, 0) {
+        // the title screen
+        None
+    } else if !main_map.contains(&x) || !main_map.contains(&y) {
+        // the final level and the intermissions
+        Some("Outside Dimension VVVVVV")
+    } else {
+        AREAS
+            .iter()
+            .find(|(_, xs, ys)| xs.contains(&x) && ys.contains(&y))
+            .map(|(name, _, _)| *name)
+    }
+}
+
+/// Where the player is, for people to read: the area if we know it, otherwise the room's
+/// coordinates.
+pub(crate) fn describe_room(room: (u32, u32)) -> String {
+    area(room).map_or_else(|| format!("Room {},{}", room.0, room.1), String::from)
+}
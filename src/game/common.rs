@@ -3,13 +3,13 @@ use std::mem::size_of;
 use std::time::Duration;
 use zerocopy::FromBytes;
 
-#[derive(Debug, FromBytes)]
-#[repr(C)]
+#[derive(Debug)]
 pub(super) struct GameObject {
     room_x: u32,
     room_y: u32,
     state: u32,
     gamestate: u32,
+    deaths: Option<u32>,
     timer: Timer<u32>,
 }
 
@@ -21,6 +21,7 @@ impl GameObject {
                 room: (self.room_x, self.room_y),
                 gamestate: self.gamestate,
                 state: self.state,
+                deaths: self.deaths,
             },
             self.timer.into(),
         )
@@ -38,6 +39,9 @@ impl GameObject {
         Self {
             timer: read_object(bytes, revision.timer_offset),
             gamestate: read_object(bytes, revision.gamestate_offset),
+            deaths: revision
+                .deaths_offset
+                .map(|offset| read_object(bytes, offset)),
             room_x: read_object(bytes, revision.room_x_offset),
             room_y: read_object(bytes, revision.room_y_offset),
             state: read_object(bytes, revision.state_offset),
@@ -45,6 +49,11 @@ impl GameObject {
     }
 }
 
+pub(super) const TIMER_SIZE: usize = size_of::<Timer<u32>>();
+
+/// `sizeof(std::string)` in libstdc++
+pub(super) const STRING_SIZE: usize = 32;
+
 #[derive(Debug, FromBytes)]
 struct Timer<T> {
     frames: T,
@@ -56,14 +65,13 @@ struct Timer<T> {
 impl<T> From<Timer<T>> for Duration
 where
     u64: From<T>,
-    u32: From<T>,
 {
     fn from(timer: Timer<T>) -> Duration {
-        Duration::new(
+        // these can be garbage (e.g. while detecting the revision), so avoid overflowing
+        Duration::from_secs(
             u64::from(timer.hours) * 3600
                 + u64::from(timer.minutes) * 60
                 + u64::from(timer.seconds),
-            1_000_000_000u32 / 30 * u32::from(timer.frames),
-        )
+        ) + Duration::from_nanos(1_000_000_000 / 30 * u64::from(timer.frames))
     }
 }
@@ -0,0 +1,250 @@
+//! Figuring out which revision of VVVVVV we're attached to.
+//!
+//! We don't have a table of VVVVVV builds, so this is all done by probing: we read the game object
+//! with each distinct layout in the revision table for a little while, and pick the one whose
+//! values look like a game that's actually running. Builds we've identified like this before are
+//! remembered by their fingerprint, so they only need probing once.
+
+use crate::game::{imp, read_cache, write_cache, Game, Revision, State};
+use anyhow::{anyhow, Result};
+use std::fmt;
+use std::time::Duration;
+
+/// how many times to read the game object with each layout
+const SAMPLES: usize = 8;
+/// VVVVVV runs at 30 FPS, so this gives us a few frames between samples
+const SAMPLE_INTERVAL: Duration = Duration::from_millis(100);
+/// a timer that goes back to less than this may just have been restarted by a new game
+const RESTARTED: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Confidence {
+    /// the build was identified before
+    Known,
+    /// exactly one layout looked best
+    High,
+    /// several layouts looked equally good, and we picked one of them
+    Low,
+}
+
+impl fmt::Display for Confidence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Confidence::Known => "known build",
+            Confidence::High => "high confidence",
+            Confidence::Low => "low confidence",
+        })
+    }
+}
+
+/// A distinct layout from the revision table.
+struct Candidate {
+    name: &'static str,
+    revision: &'static Revision,
+}
+
+/// Whether two revisions read the game object the same way.
+fn same_fields(a: &Revision, b: &Revision) -> bool {
+    a.room_x_offset == b.room_x_offset
+        && a.room_y_offset == b.room_y_offset
+        && a.state_offset == b.state_offset
+        && a.gamestate_offset == b.gamestate_offset
+        && a.timer_offset == b.timer_offset
+        && a.deaths_offset == b.deaths_offset
+        && a.savetime_offset == b.savetime_offset
+        && a.playing_states == b.playing_states
+}
+
+/// Whether a single reading of the game object makes sense at all.
+fn is_plausible((state, time): &(State, Duration)) -> bool {
+    // The main map's rooms are numbered from 100 to 119 (and custom levels start at 100 too), but
+    // the final level and the intermissions are elsewhere, in the 40s and 50s, and the title screen
+    // leaves them at 0. So all we can rule out is something past the main map.
+    state.room.0 < 120
+        && state.room.1 < 120
+        && state.gamestate < 16
+        && state.state < 10_000
+        && time.as_secs() < 1000 * 3600
+}
+
+/// Why a layout was ruled out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Rejected {
+    Implausible,
+    /// the timer went backwards, and not just back to the start
+    TimeWentBackwards,
+}
+
+/// How convincing a series of readings is with `revision`, or why it's clearly wrong.
+fn score(revision: &Revision, samples: &[(State, Duration)]) -> Result<u32, Rejected> {
+    if !samples.iter().all(is_plausible) {
+        return Err(Rejected::Implausible);
+    }
+    let mut score = 0;
+    for pair in samples.windows(2) {
+        let ((old, old_time), (_, new_time)) = (&pair[0], &pair[1]);
+        if new_time < old_time {
+            // starting a new game puts the timer back to zero
+            if *new_time < RESTARTED {
+                continue;
+            }
+            return Err(Rejected::TimeWentBackwards);
+        }
+        // the timer only runs while playing
+        if new_time > old_time && revision.is_playing_state(old.gamestate) {
+            score += 2;
+        }
+    }
+    for (state, _) in samples {
+        // all-zero memory is plausible, so we want some evidence that isn't
+        if state.room.0 != 0 && state.room.1 != 0 {
+            score += 1;
+        }
+        // most of the game happens on the main map
+        if (100..120).contains(&state.room.0) && (100..120).contains(&state.room.1) {
+            score += 1;
+        }
+        if state.gamestate != 0 {
+            score += 1;
+        }
+    }
+    Ok(score)
+}
+
+impl Game {
+    /// Figure out which revision of VVVVVV we're attached to.
+    pub(crate) fn detect_revision(&self) -> Result<(&'static str, &'static Revision, Confidence)> {
+        let fingerprint = imp::fingerprint(self.pid)
+            .map_err(|e| log::debug!("couldn't fingerprint pid {}: {:#}", self.pid, e))
+            .ok();
+        if let Some((fingerprint, name)) = fingerprint
+            .as_ref()
+            .and_then(|fingerprint| Some((fingerprint, read_cache("revisions", fingerprint)?)))
+        {
+            if let Some((name, revision)) = Revision::layouts().find(|(n, _)| *n == name) {
+                // in case it was remembered wrongly, or the table has changed since
+                if imp::read_game_object(&self.handle, revision).is_ok_and(|s| is_plausible(&s)) {
+                    return Ok((name, revision, Confidence::Known));
+                }
+                log::info!(
+                    "build {} was remembered as revision {}, which doesn't fit; detecting again",
+                    fingerprint,
+                    name
+                );
+            }
+        }
+
+        let candidates = Revision::layouts()
+            .map(|(name, revision)| Candidate { name, revision })
+            .collect::<Vec<_>>();
+        let mut samples = vec![vec![]; candidates.len()];
+        let mut error = None;
+        for i in 0..SAMPLES {
+            if i > 0 {
+                std::thread::sleep(SAMPLE_INTERVAL);
+            }
+            for (candidate, samples) in candidates.iter().zip(&mut samples) {
+                match imp::read_game_object(&self.handle, candidate.revision) {
+                    Ok(sample) => samples.push(sample),
+                    Err(e) => {
+                        error.get_or_insert(e);
+                    }
+                }
+            }
+        }
+        // if no layout could be read at all, why is more useful than their scores
+        if samples.iter().all(Vec::is_empty) {
+            if let Some(e) = error {
+                return Err(e.context("couldn't read the game object"));
+            }
+        }
+
+        let mut went_backwards = false;
+        let mut scores = candidates
+            .iter()
+            .zip(&samples)
+            .filter(|(_, samples)| samples.len() == SAMPLES)
+            .filter_map(
+                |(candidate, samples)| match score(candidate.revision, samples) {
+                    Ok(score) => Some((candidate, score)),
+                    Err(rejected) => {
+                        log::debug!("revision {} ruled out: {:?}", candidate.name, rejected);
+                        went_backwards |= rejected == Rejected::TimeWentBackwards;
+                        None
+                    }
+                },
+            )
+            .collect::<Vec<_>>();
+        // prefer layouts that a tag points to when the scores are tied
+        scores.sort_by_key(|(candidate, score)| {
+            (std::cmp::Reverse(*score), candidate.name.len() == 40)
+        });
+        for (candidate, score) in &scores {
+            log::debug!("revision {} scored {}", candidate.name, score);
+        }
+
+        let (best, best_score) = scores
+            .first()
+            .ok_or_else(|| anyhow!("no known revision matches the game's memory"))?;
+        // layouts that only differ in the size of the game object (i.e. in fields after the ones
+        // we read) will always tie, and it doesn't matter which one we pick.
+        let confidence = if *best_score == 0
+            || scores.iter().skip(1).any(|(candidate, score)| {
+                score == best_score && !same_fields(candidate.revision, best.revision)
+            }) {
+            Confidence::Low
+        } else {
+            Confidence::High
+        };
+        if let (Some(fingerprint), Confidence::High) = (&fingerprint, confidence) {
+            log::info!("build {} is revision {}", fingerprint, best.name);
+            // If the timer went backwards for a layout, something odd happened while we were
+            // sampling (the right layout might even have been ruled out), so don't count on it.
+            if went_backwards {
+                log::debug!("not remembering that, since a timer went backwards");
+            } else {
+                write_cache("revisions", fingerprint, best.name);
+            }
+        }
+        Ok((best.name, best.revision, confidence))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Readings in the middle of a run, with the timer at each of `times` (in milliseconds).
+    fn samples(room: (u32, u32), times: &[u64]) -> Vec<(State, Duration)> {
+        times
+            .iter()
+            .map(|&time| {
+                let state = State {
+                    room,
+                    gamestate: 0,
+                    state: 0,
+                    deaths: None,
+                };
+                (state, Duration::from_millis(time))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn scores_readings() {
+        let (_, revision) = Revision::layouts().next().unwrap();
+        let running = score(revision, &samples((115, 100), &[5000, 5100, 5200, 5300]));
+        assert!(running.is_ok_and(|score| score > 0));
+        // a new game, not a reason to rule it out
+        let restarted = score(revision, &samples((115, 100), &[5000, 5100, 0, 100]));
+        assert!(restarted.is_ok_and(|score| score > 0));
+        assert_eq!(
+            score(revision, &samples((115, 100), &[5000, 5100, 3000, 3100])),
+            Err(Rejected::TimeWentBackwards)
+        );
+        assert_eq!(
+            score(revision, &samples((115, 300), &[5000, 5100, 5200, 5300])),
+            Err(Rejected::Implausible)
+        );
+    }
+}
@@ -0,0 +1,236 @@
+#![cfg(target_os = "linux")]
+
+//! Just enough of an ELF parser to look up symbols in a VVVVVV executable (or its separate debug
+//! file) and figure out where it was loaded.
+//!
+//! Only 64-bit little-endian files are supported, which covers every platform VVVVVV ships for on
+//! Linux.
+
+use anyhow::{anyhow, bail, Result};
+use std::fmt::Write;
+use std::mem::size_of;
+use zerocopy::FromBytes;
+
+const ET_DYN: u16 = 3;
+const PT_LOAD: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+const SHT_NOTE: u32 = 7;
+const SHT_DYNSYM: u32 = 11;
+const SHN_UNDEF: u16 = 0;
+const NT_GNU_BUILD_ID: u32 = 3;
+
+#[derive(Debug, FromBytes)]
+#[repr(C)]
+struct Header {
+    ident: [u8; 16],
+    kind: u16,
+    machine: u16,
+    version: u32,
+    entry: u64,
+    phoff: u64,
+    shoff: u64,
+    flags: u32,
+    ehsize: u16,
+    phentsize: u16,
+    phnum: u16,
+    shentsize: u16,
+    shnum: u16,
+    shstrndx: u16,
+}
+
+#[derive(Debug, FromBytes)]
+#[repr(C)]
+struct ProgramHeader {
+    kind: u32,
+    flags: u32,
+    offset: u64,
+    vaddr: u64,
+    paddr: u64,
+    filesz: u64,
+    memsz: u64,
+    align: u64,
+}
+
+#[derive(Debug, FromBytes)]
+#[repr(C)]
+struct SectionHeader {
+    name: u32,
+    kind: u32,
+    flags: u64,
+    addr: u64,
+    offset: u64,
+    size: u64,
+    link: u32,
+    info: u32,
+    addralign: u64,
+    entsize: u64,
+}
+
+#[derive(Debug, FromBytes)]
+#[repr(C)]
+struct Symbol {
+    name: u32,
+    info: u8,
+    other: u8,
+    shndx: u16,
+    value: u64,
+    size: u64,
+}
+
+#[derive(Debug, FromBytes)]
+#[repr(C)]
+struct NoteHeader {
+    namesz: u32,
+    descsz: u32,
+    kind: u32,
+}
+
+pub(super) struct Elf<'a> {
+    data: &'a [u8],
+    header: Header,
+    sections: Vec<SectionHeader>,
+}
+
+fn read<T: FromBytes>(data: &[u8], offset: u64) -> Result<T> {
+    slice(data, offset, size_of::<T>() as u64)
+        .map(|bytes| T::read_from(bytes).expect("slice has the size of T"))
+}
+
+fn slice(data: &[u8], offset: u64, size: u64) -> Result<&[u8]> {
+    let offset = usize::try_from(offset)?;
+    let size = usize::try_from(size)?;
+    offset
+        .checked_add(size)
+        .and_then(|end| data.get(offset..end))
+        .ok_or_else(|| anyhow!("truncated ELF file"))
+}
+
+/// The offset of the `i`th entry of a table at `offset` whose entries are `size` bytes, as found in
+/// a (possibly corrupt) file.
+fn entry(offset: u64, i: u64, size: u64) -> Result<u64> {
+    i.checked_mul(size)
+        .and_then(|n| offset.checked_add(n))
+        .ok_or_else(|| anyhow!("truncated ELF file"))
+}
+
+/// Get the NUL-terminated string at `offset` in `data`.
+fn c_str(data: &[u8], offset: usize) -> Option<&[u8]> {
+    let data = data.get(offset..)?;
+    Some(&data[..data.iter().position(|&b| b == 0)?])
+}
+
+fn align4(n: u64) -> u64 {
+    (n + 3) & !3
+}
+
+impl<'a> Elf<'a> {
+    pub(super) fn parse(data: &'a [u8]) -> Result<Self> {
+        let header: Header = read(data, 0)?;
+        if &header.ident[..4] != b"\x7fELF" {
+            bail!("not an ELF file");
+        }
+        if header.ident[4] != 2 || header.ident[5] != 1 {
+            bail!("only 64-bit little-endian ELF files are supported");
+        }
+        let sections = (0..u64::from(header.shnum))
+            .map(|i| read(data, entry(header.shoff, i, u64::from(header.shentsize))?))
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            data,
+            header,
+            sections,
+        })
+    }
+
+    /// Whether the file is position-independent, i.e. its addresses are relative to wherever it
+    /// gets loaded.
+    pub(super) fn is_pie(&self) -> bool {
+        self.header.kind == ET_DYN
+    }
+
+    /// Get the `PT_LOAD` segment with the lowest address, as `(file offset, virtual address)`.
+    pub(super) fn first_load_segment(&self) -> Result<(u64, u64)> {
+        let mut first = None;
+        for i in 0..u64::from(self.header.phnum) {
+            let ph: ProgramHeader = read(
+                self.data,
+                entry(self.header.phoff, i, u64::from(self.header.phentsize))?,
+            )?;
+            if ph.kind == PT_LOAD && first.is_none_or(|(_, vaddr)| ph.vaddr < vaddr) {
+                first = Some((ph.offset, ph.vaddr));
+            }
+        }
+        first.ok_or_else(|| anyhow!("no loadable segments"))
+    }
+
+    fn section_data(&self, section: &SectionHeader) -> Result<&'a [u8]> {
+        slice(self.data, section.offset, section.size)
+    }
+
+    fn section_name(&self, section: &SectionHeader) -> Option<&'a [u8]> {
+        let names = self.sections.get(usize::from(self.header.shstrndx))?;
+        c_str(self.section_data(names).ok()?, section.name as usize)
+    }
+
+    fn section(&self, name: &str) -> Option<&SectionHeader> {
+        self.sections
+            .iter()
+            .find(|s| self.section_name(s) == Some(name.as_bytes()))
+    }
+
+    /// Look up the value (i.e. the link-time address) of a symbol in `.symtab` or `.dynsym`.
+    pub(super) fn symbol(&self, name: &str) -> Result<Option<u64>> {
+        for table in self
+            .sections
+            .iter()
+            .filter(|s| s.kind == SHT_SYMTAB || s.kind == SHT_DYNSYM)
+        {
+            let strings = self
+                .sections
+                .get(table.link as usize)
+                .ok_or_else(|| anyhow!("bad symbol table string index"))?;
+            let strings = self.section_data(strings)?;
+            let entsize = table.entsize.max(size_of::<Symbol>() as u64);
+            for i in 0..table.size / entsize {
+                let symbol: Symbol = read(self.data, entry(table.offset, i, entsize)?)?;
+                if symbol.shndx != SHN_UNDEF
+                    && c_str(strings, symbol.name as usize) == Some(name.as_bytes())
+                {
+                    return Ok(Some(symbol.value));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// The GNU build ID of the file, as a hex string.
+    pub(super) fn build_id(&self) -> Option<String> {
+        for section in self.sections.iter().filter(|s| s.kind == SHT_NOTE) {
+            let data = self.section_data(section).ok()?;
+            let mut offset = 0;
+            while let Ok(note) = read::<NoteHeader>(data, offset) {
+                let name = offset + size_of::<NoteHeader>() as u64;
+                let desc = name + align4(u64::from(note.namesz));
+                if note.kind == NT_GNU_BUILD_ID
+                    && slice(data, name, u64::from(note.namesz)).ok()? == b"GNU\0"
+                {
+                    let id = slice(data, desc, u64::from(note.descsz)).ok()?;
+                    let mut hex = String::new();
+                    for b in id {
+                        write!(hex, "{b:02x}").unwrap();
+                    }
+                    return Some(hex);
+                }
+                offset = desc + align4(u64::from(note.descsz));
+            }
+        }
+        None
+    }
+
+    /// The file name from the `.gnu_debuglink` section, which names the separate file that the
+    /// debug info (including `.symtab`) was stripped into.
+    pub(super) fn debuglink(&self) -> Option<String> {
+        let data = self.section_data(self.section(".gnu_debuglink")?).ok()?;
+        Some(String::from_utf8_lossy(c_str(data, 0)?).into_owned())
+    }
+}
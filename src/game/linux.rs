@@ -1,61 +1,274 @@
 #![cfg(target_os = "linux")]
 
-use crate::game::common::GameObject;
-use crate::game::{Revision, State};
-use anyhow::{anyhow, Result};
+use crate::game::common::{GameObject, STRING_SIZE};
+use crate::game::elf::Elf;
+use crate::game::{read_cache, write_cache, Revision, State};
+use anyhow::{anyhow, Context, Result};
 use read_process_memory::{CopyAddress, Pid, ProcessHandle};
-use std::process::Command;
+use regex::bytes::Regex;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::Path;
 use std::time::Duration;
 
 pub(super) struct Handle {
     process: ProcessHandle,
-    address: usize,
+    location: Location,
 }
 
-const DEFAULT_ADDRESS: usize = 0x854dc0;
+/// What we know about where the game object is.
+enum Location {
+    /// the address of the game object itself
+    Object(usize),
+    /// the address of its `savetime` member, which is at a different offset in each revision
+    Savetime(usize),
+}
+
+impl Handle {
+    fn address(&self, revision: &Revision) -> Result<usize> {
+        match self.location {
+            Location::Object(address) => Ok(address),
+            Location::Savetime(address) => revision
+                .savetime_offset
+                .map(|offset| address - offset)
+                .ok_or_else(|| {
+                    anyhow!(
+                        "this VVVVVV has no symbols, and the revision table doesn't have the offset \
+                         of Game::savetime to find the game object from without them; load a \
+                         table that parse_vvvvvv_src generated on Linux with --revisions-file, or \
+                         use --revision with --offset savetime=N"
+                    )
+                }),
+        }
+    }
+}
+
+/// the symbol for VVVVVV's global `Game` object
+const SYMBOL: &str = "game";
+
+/// Where separate debug files get installed (see "Separate Debug Files" in the gdb manual).
+const DEBUG_DIR: &str = "/usr/lib/debug";
+
+/// Find the link-time address of `game` in `exe`, or in its separate debug file if it's stripped.
+fn find_symbol(exe: &Elf, exe_path: &Path) -> Result<u64> {
+    if let Some(address) = exe.symbol(SYMBOL)? {
+        return Ok(address);
+    }
+    let mut candidates = vec![];
+    if let Some(build_id) = exe.build_id() {
+        let (dir, file) = build_id.split_at(2.min(build_id.len()));
+        candidates.push(Path::new(DEBUG_DIR).join(format!(".build-id/{dir}/{file}.debug")));
+    }
+    if let Some(name) = exe.debuglink() {
+        let dir = exe_path.parent().unwrap_or(Path::new("/"));
+        candidates.push(dir.join(&name));
+        candidates.push(dir.join(".debug").join(&name));
+        candidates.push(
+            Path::new(DEBUG_DIR)
+                .join(dir.strip_prefix("/").unwrap_or(dir))
+                .join(&name),
+        );
+    }
+    for path in candidates {
+        let Ok(data) = fs::read(&path) else { continue };
+        log::debug!("looking for `{}` in {}", SYMBOL, path.display());
+        // We don't check the debuglink CRC; a debug file with the wrong symbols will just give us
+        // a game object that reads as garbage.
+        match Elf::parse(&data).and_then(|debug| debug.symbol(SYMBOL)) {
+            Ok(Some(address)) => return Ok(address),
+            Ok(None) => {}
+            Err(e) => log::debug!("skipping {}: {:#}", path.display(), e),
+        }
+    }
+    Err(anyhow!(
+        "no `{}` symbol in {} and no debug file with it",
+        SYMBOL,
+        exe_path.display()
+    ))
+}
+
+/// A line from `/proc/<pid>/maps`.
+struct Mapping {
+    start: u64,
+    end: u64,
+    readable: bool,
+    writable: bool,
+    offset: u64,
+    path: String,
+}
+
+fn read_maps(pid: Pid) -> Result<Vec<Mapping>> {
+    let maps = fs::read_to_string(format!("/proc/{pid}/maps"))?;
+    let mut mappings = vec![];
+    for line in maps.lines() {
+        // e.g. 55d0c1a00000-55d0c1a2c000 r--p 00000000 fd:01 1234567    /path/to/VVVVVV
+        let mut fields = line.split_whitespace();
+        let (Some(range), Some(perms), Some(offset), Some(_dev), Some(_inode)) = (
+            fields.next(),
+            fields.next(),
+            fields.next(),
+            fields.next(),
+            fields.next(),
+        ) else {
+            return Err(anyhow!("bad line in /proc/{pid}/maps: {line:?}"));
+        };
+        let (start, end) = range
+            .split_once('-')
+            .ok_or_else(|| anyhow!("bad range in /proc/{pid}/maps: {range:?}"))?;
+        let path = fields.collect::<Vec<_>>().join(" ");
+        mappings.push(Mapping {
+            start: u64::from_str_radix(start, 16)?,
+            end: u64::from_str_radix(end, 16)?,
+            readable: perms.starts_with('r'),
+            writable: perms[1..].starts_with('w'),
+            offset: u64::from_str_radix(offset, 16)?,
+            path: path.strip_suffix(" (deleted)").unwrap_or(&path).to_string(),
+        });
+    }
+    Ok(mappings)
+}
 
+/// Find where the executable's first loadable segment was mapped into the process.
+fn load_address(pid: Pid, exe_path: &Path, file_offset: u64) -> Result<u64> {
+    read_maps(pid)?
+        .into_iter()
+        .find(|m| Path::new(&m.path) == exe_path && m.offset == file_offset)
+        .map(|m| m.start)
+        .ok_or_else(|| anyhow!("{} is not mapped into pid {}", exe_path.display(), pid))
+}
+
+/// Get the address of the game object from the executable's symbols.
 fn get_address(pid: Pid) -> Result<usize> {
-    let gdb = Command::new("gdb")
-        .args(["--nw", "--nx"]) // no window, don't read .gdbinit
-        .arg(format!("--pid={pid}")) // attach to pid
-        .args(["--ex", "p (unsigned long long)&game"]) // print address of game object
-        .args(["--ex", "set confirm off", "--ex", "q"]) // quit without confirming
-        .output()?;
-    if !gdb.status.success() {
-        return Err(anyhow!("gdb failed with status {}", gdb.status));
-    }
-    let stdout = String::from_utf8_lossy(&gdb.stdout);
-    // gdb should output a line like
-    //     $1 = 1398429384
-    // and that gives us the address of `game`.
-    let pat = "\n$1 = ";
-    let i = stdout
-        .find(pat)
-        .ok_or_else(|| anyhow!("no address in gdb output"))?;
-    let stdout = &stdout[i + pat.len()..];
-    let stdout = &stdout[..stdout
-        .find('\n')
-        .ok_or_else(|| anyhow!("weird gdb output"))?];
-    Ok(stdout.parse::<usize>()?)
+    let exe_path = fs::read_link(format!("/proc/{pid}/exe"))
+        .with_context(|| format!("failed to read /proc/{pid}/exe"))?;
+    let data = fs::read(format!("/proc/{pid}/exe"))
+        .with_context(|| format!("failed to read {}", exe_path.display()))?;
+    let exe = Elf::parse(&data)?;
+
+    // remember the symbol for each build ID we've seen, so we don't have to go looking for debug
+    // files every time.
+    let build_id = exe.build_id();
+    let cached = build_id
+        .as_ref()
+        .and_then(|id| read_cache("symbols", id))
+        .and_then(|s| s.parse().ok());
+    let symbol = if let Some(symbol) = cached {
+        symbol
+    } else {
+        let symbol = find_symbol(&exe, &exe_path)?;
+        if let Some(id) = &build_id {
+            write_cache("symbols", id, &symbol.to_string());
+        }
+        symbol
+    };
+
+    let address = if exe.is_pie() {
+        // the page containing the first segment gets mapped at the (page-aligned) load address
+        // plus the segment's page-aligned virtual address.
+        let (offset, vaddr) = exe.first_load_segment()?;
+        let start = load_address(pid, &exe_path, offset & !(PAGE_SIZE - 1))?;
+        start - (vaddr & !(PAGE_SIZE - 1)) + symbol
+    } else {
+        symbol
+    };
+    Ok(usize::try_from(address)?)
 }
 
-pub(super) fn find_game_object(pid: Pid) -> Result<Handle> {
-    Ok(Handle {
-        process: ProcessHandle::try_from(pid)?,
-        address: match get_address(pid) {
-            Ok(a) => a,
-            Err(e) => {
-                log::warn!(
-                    "couldn't get address from gdb: {e}. defaulting to 0x{DEFAULT_ADDRESS:x}"
-                );
-                DEFAULT_ADDRESS
+/// the granularity of memory mappings; executables are linked so that segments line up with it.
+const PAGE_SIZE: u64 = 0x1000;
+
+/// Scan the process's writable memory for the game object's `savetime` and `savearea` strings.
+///
+/// This is the same trick the macOS backend uses, but for libstdc++, where a `std::string` is a
+/// pointer, a length, and a 16-byte buffer that the pointer points to when the string is short
+/// enough. So we look for a 5-byte "00:00" followed by a 7-byte "nowhere", each pointing to its
+/// own buffer. That only works until the game first saves, since that changes `savetime`.
+///
+/// The game object is a global (or, before 2.3, a local in `main`), so it's always in the
+/// executable's `.bss`/`.data` or on the stack, both of which show up as anonymous or
+/// executable-backed read-write mappings.
+fn scan_for_savetime(pid: Pid, process: &ProcessHandle) -> Result<usize> {
+    const CHUNK_SIZE: usize = 1 << 20;
+    const OVERLAP: usize = 64;
+    let regex =
+        Regex::new(r"(?s-u).{8}\x05\x00{7}00:00\x00.{10}.{8}\x07\x00{7}nowhere\x00").unwrap();
+    let mut buf = vec![0; CHUNK_SIZE];
+    for mapping in read_maps(pid)? {
+        // device mappings (e.g. the GPU) can be huge and aren't worth reading
+        if !mapping.readable || !mapping.writable || mapping.path.starts_with("/dev/") {
+            continue;
+        }
+        let start = usize::try_from(mapping.start)?;
+        let end = usize::try_from(mapping.end)?;
+        for chunk in (start..end).step_by(CHUNK_SIZE - OVERLAP) {
+            let buf = &mut buf[..CHUNK_SIZE.min(end - chunk)];
+            if process.copy_address(chunk, buf).is_err() {
+                continue;
             }
+            for m in regex.find_iter(buf) {
+                let address = chunk + m.start();
+                let pointer = |offset: usize| {
+                    usize::from_ne_bytes(buf[m.start() + offset..][..8].try_into().unwrap())
+                };
+                if address % 8 == 0
+                    && pointer(0) == address + 16
+                    && pointer(STRING_SIZE) == address + STRING_SIZE + 16
+                {
+                    return Ok(address);
+                }
+            }
+        }
+    }
+    Err(anyhow!("couldn't find savetime/savearea in memory"))
+}
+
+pub(super) fn find_game_object(pid: Pid) -> Result<Handle> {
+    let process = ProcessHandle::try_from(pid)?;
+    let location = match get_address(pid) {
+        Ok(address) => Location::Object(address),
+        Err(e) => {
+            log::info!("{:#}; scanning memory instead", e);
+            Location::Savetime(scan_for_savetime(pid, &process)?)
+        }
+    };
+    Ok(Handle { process, location })
+}
+
+/// Identify the VVVVVV build running as `pid`, by its build ID if it has one or otherwise by
+/// hashing the executable.
+pub(super) fn fingerprint(pid: Pid) -> Result<String> {
+    let data = fs::read(format!("/proc/{pid}/exe"))?;
+    Ok(
+        match Elf::parse(&data).ok().and_then(|exe| exe.build_id()) {
+            Some(build_id) => build_id,
+            None => format!("sha256-{:x}", Sha256::digest(&data)),
         },
-    })
+    )
+}
+
+/// Whether `pid` still refers to a live process.
+///
+/// A process that has exited but not yet been reaped by its parent still has an entry in `/proc`,
+/// so we have to look at its state rather than just checking that the directory exists.
+pub(super) fn is_running(pid: Pid) -> bool {
+    let Ok(stat) = fs::read_to_string(format!("/proc/{pid}/stat")) else {
+        return false;
+    };
+    // the state comes right after the command name, which is in parentheses and may itself
+    // contain spaces or parentheses.
+    match stat
+        .rfind(')')
+        .and_then(|i| stat[i + 1..].split_whitespace().next())
+    {
+        Some(state) => !matches!(state, "Z" | "X" | "x"),
+        None => false,
+    }
 }
 
 pub(super) fn read_game_object(handle: &Handle, revision: &Revision) -> Result<(State, Duration)> {
     let mut buf = vec![0; revision.game_object_size()];
-    handle.process.copy_address(handle.address, &mut buf)?;
+    handle
+        .process
+        .copy_address(handle.address(revision)?, &mut buf)?;
     Ok(GameObject::from_bytes(revision, &buf).into_state())
 }
@@ -5,6 +5,9 @@ use crate::game::{Revision, State};
 use anyhow::{anyhow, Result};
 use read_process_memory::{CopyAddress, Pid, ProcessHandle};
 use regex::bytes::Regex;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::process::Command;
 use std::time::Duration;
 
 pub(super) struct Handle {
@@ -64,6 +67,32 @@ pub(super) fn find_game_object(pid: Pid) -> Result<Handle> {
     Err(anyhow!("failed to find game object"))
 }
 
+/// Identify the VVVVVV build running as `pid` by hashing its executable.
+pub(super) fn fingerprint(pid: Pid) -> Result<String> {
+    let output = Command::new("ps")
+        .args(["-o", "comm=", "-p", &pid.to_string()])
+        .output()?;
+    if !output.status.success() {
+        return Err(anyhow!("ps failed with {}", output.status));
+    }
+    let path = String::from_utf8(output.stdout)?;
+    let data = fs::read(path.trim_end())?;
+    Ok(format!("sha256-{:x}", Sha256::digest(data)))
+}
+
+/// Whether `pid` still refers to a live (non-zombie) process.
+pub(super) fn is_running(pid: Pid) -> bool {
+    match Command::new("ps")
+        .args(["-o", "stat=", "-p", &pid.to_string()])
+        .output()
+    {
+        Ok(output) => {
+            output.status.success() && !output.stdout.trim_ascii_start().starts_with(b"Z")
+        }
+        Err(_) => false,
+    }
+}
+
 pub(super) fn read_game_object(handle: &Handle, revision: &Revision) -> Result<(State, Duration)> {
     let mut buf = vec![0; revision.game_object_size()];
     handle.process.copy_address(handle.addr, &mut buf)?;
@@ -1,21 +1,35 @@
+mod areas;
 mod common;
+mod detect;
+mod elf;
 mod linux;
 mod macos;
+mod overrides;
 mod revisions;
+mod revisions_file;
 
 #[cfg(target_os = "linux")]
 use linux as imp;
 #[cfg(target_os = "macos")]
 use macos as imp;
 
-use anyhow::Result;
+pub(crate) use areas::describe_room;
+pub(crate) use detect::Confidence;
+pub(crate) use overrides::{OffsetOverride, Overrides, PlayingStates};
+
+use anyhow::{anyhow, bail, Context, Result};
 use debug_ignore::DebugIgnore;
 use read_process_memory::Pid;
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fmt;
+use std::fs;
 use std::ops::RangeInclusive;
-use std::sync::Once;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
 use std::time::Duration;
 
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Revision {
     game_object_size: usize,
     room_x_offset: usize,
@@ -23,20 +37,184 @@ pub struct Revision {
     state_offset: usize,
     gamestate_offset: usize,
     timer_offset: usize,
-    playing_states: Vec<u32>,
+    /// where `Game::deathcounts` is, if the table has it
+    deaths_offset: Option<usize>,
+    /// where `Game::savetime` is with libstdc++, for finding the game object from it on Linux, if
+    /// the table has it
+    savetime_offset: Option<usize>,
+    playing_states: [u32; 5],
+}
+
+/// A commit in the VVVVVV repository, and the layout `Game` has in it.
+pub(crate) struct Commit {
+    id: &'static str,
+    /// index into `Table::layouts`
+    layout: u8,
+    /// position in the history of the master branch, newest first
+    order: u16,
+}
+
+/// The revisions we know about.
+struct Table {
+    layouts: &'static [Revision],
+    tags: &'static [(&'static str, u8)],
+    /// sorted by ID
+    commits: &'static [Commit],
+}
+
+static TABLE: OnceLock<Table> = OnceLock::new();
+
+/// The table from `--revisions-file` if there was one, otherwise the one compiled in.
+fn table() -> &'static Table {
+    TABLE.get_or_init(|| Table {
+        layouts: &revisions::LAYOUTS,
+        tags: &revisions::TAGS,
+        commits: &revisions::COMMITS,
+    })
+}
+
+impl Commit {
+    /// All the commits whose IDs start with `prefix`.
+    fn matching(prefix: &str) -> &'static [Self] {
+        let commits = table().commits;
+        let start = commits.partition_point(|commit| commit.id < prefix);
+        let count = commits[start..]
+            .iter()
+            .take_while(|commit| commit.id.starts_with(prefix))
+            .count();
+        &commits[start..start + count]
+    }
+}
+
+/// What a revision name refers to.
+pub(crate) struct Resolved {
+    /// the full commit ID, if the name was a commit rather than a tag
+    pub(crate) commit: Option<&'static str>,
+    /// which layout it has (layouts are numbered in the order they appear in the table)
+    pub(crate) layout: usize,
+    pub(crate) revision: &'static Revision,
+}
+
+impl Resolved {
+    fn new(commit: Option<&'static str>, layout: u8) -> Self {
+        let layout = usize::from(layout);
+        Self {
+            commit,
+            layout,
+            revision: &table().layouts[layout],
+        }
+    }
+}
+
+/// The tags (and "master") we know about, with the index of their layout.
+pub(crate) fn tags() -> impl Iterator<Item = (&'static str, usize)> {
+    table()
+        .tags
+        .iter()
+        .map(|(name, layout)| (*name, usize::from(*layout)))
+}
+
+/// Every commit we know about, with the index of its layout, newest first.
+pub(crate) fn history() -> Vec<(&'static str, usize)> {
+    let mut commits = table().commits.iter().collect::<Vec<_>>();
+    commits.sort_unstable_by_key(|commit| commit.order);
+    commits
+        .into_iter()
+        .map(|commit| (commit.id, usize::from(commit.layout)))
+        .collect()
 }
 
 impl Revision {
-    pub fn get(name: &str) -> Option<&'static Self> {
-        // ugh rust doesnt support const HashMaps or Vecs
-        static mut REVISIONS: Option<HashMap<&'static str, Revision>> = None;
-        static REVISIONS_ONCE: Once = Once::new();
-        REVISIONS_ONCE.call_once(|| {
-            // SAFETY: we only access REVISIONS here and below
-            unsafe { REVISIONS = Some(revisions::get()) }
-        });
-        // SAFETY: we only modify REVISIONS in the Once above
-        unsafe { REVISIONS.as_ref() }.unwrap().get(name)
+    /// Look up a revision by tag (e.g. "2.3"), "master", (abbreviated) commit ID, or the output of
+    /// `git describe` (e.g. "2.3-451-g48cddf57").
+    pub(crate) fn resolve(name: &str) -> Result<Resolved> {
+        let table = table();
+        if let Some((_, layout)) = table.tags.iter().find(|(tag, _)| *tag == name) {
+            return Ok(Resolved::new(None, *layout));
+        }
+        let name = name.strip_suffix("-dirty").unwrap_or(name);
+        // `git describe` gives TAG-COUNT-gID
+        let id = name.rsplit_once("-g").map_or(name, |(_, id)| id);
+        // git won't abbreviate to less than 4 characters either
+        if id.len() < 4 || !id.bytes().all(|b| b.is_ascii_hexdigit()) {
+            bail!("no such revision {name:?} (expected a tag or a commit ID)");
+        }
+        match Commit::matching(&id.to_ascii_lowercase()) {
+            [] => bail!("no such revision {name:?}"),
+            [commit] => Ok(Resolved::new(Some(commit.id), commit.layout)),
+            commits => bail!(
+                "{name:?} is ambiguous; it could be any of {}",
+                commits
+                    .iter()
+                    .map(|commit| commit.id)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        }
+    }
+
+    /// Every distinct layout, with a name for it: a tag if there is one, otherwise the newest
+    /// commit that has it.
+    fn layouts() -> impl Iterator<Item = (&'static str, &'static Self)> {
+        let table = table();
+        table.layouts.iter().enumerate().map(|(i, revision)| {
+            let tag = table
+                .tags
+                .iter()
+                .find(|(_, layout)| usize::from(*layout) == i)
+                .map(|(tag, _)| *tag);
+            let name = tag.or_else(|| {
+                table
+                    .commits
+                    .iter()
+                    .filter(|commit| usize::from(commit.layout) == i)
+                    .min_by_key(|commit| commit.order)
+                    .map(|commit| commit.id)
+            });
+            (name.unwrap_or_default(), revision)
+        })
+    }
+
+    /// Use the revisions from a file written by `parse_vvvvvv_src` instead of the ones compiled in.
+    ///
+    /// This has to happen before anything else looks up a revision.
+    pub(crate) fn load_table(path: &Path) -> Result<()> {
+        let loaded = revisions_file::load(path)
+            .with_context(|| format!("failed to load revisions from {}", path.display()))?;
+        log::info!(
+            "loaded {} revisions ({} layouts) from {}",
+            loaded.commits.len(),
+            loaded.layouts.len(),
+            path.display()
+        );
+        TABLE
+            .set(loaded)
+            .map_err(|_| anyhow!("revisions were already loaded"))
+    }
+
+    /// Make sure every field we read is inside the game object.
+    fn validate(&self) -> Result<()> {
+        let fields = [
+            ("room_x", Some(self.room_x_offset), 4),
+            ("room_y", Some(self.room_y_offset), 4),
+            ("state", Some(self.state_offset), 4),
+            ("gamestate", Some(self.gamestate_offset), 4),
+            ("timer", Some(self.timer_offset), common::TIMER_SIZE),
+            ("deaths", self.deaths_offset, 4),
+            ("savetime", self.savetime_offset, common::STRING_SIZE),
+        ];
+        for (name, offset, size) in fields {
+            let Some(offset) = offset else { continue };
+            if offset + size > self.game_object_size {
+                bail!(
+                    "{} (at offset {}) doesn't fit in a {}-byte game object",
+                    name,
+                    offset,
+                    self.game_object_size
+                );
+            }
+        }
+        Ok(())
     }
 
     pub(super) fn game_object_size(&self) -> usize {
@@ -44,12 +222,65 @@ impl Revision {
     }
 }
 
+impl fmt::Display for Revision {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let optional =
+            |offset: Option<usize>| offset.map_or_else(|| "unknown".to_owned(), |o| o.to_string());
+        writeln!(f, "game object size: {} bytes", self.game_object_size)?;
+        writeln!(
+            f,
+            "offsets: room_x {}, room_y {}, state {}, gamestate {}, timer {}, deaths {}, savetime {}",
+            self.room_x_offset,
+            self.room_y_offset,
+            self.state_offset,
+            self.gamestate_offset,
+            self.timer_offset,
+            optional(self.deaths_offset),
+            optional(self.savetime_offset)
+        )?;
+        write!(
+            f,
+            "playing states: {}",
+            self.playing_states
+                .map(|state| state.to_string())
+                .join(", ")
+        )
+    }
+}
+
 impl Revision {
     fn is_playing_state(&self, state: u32) -> bool {
         self.playing_states.contains(&state)
     }
 }
 
+/// Where we remember things about VVVVVV builds we've seen before.
+fn cache_path(kind: &str, key: &str) -> Option<PathBuf> {
+    let cache = env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))?;
+    Some(cache.join("vitellary").join(kind).join(key))
+}
+
+fn read_cache(kind: &str, key: &str) -> Option<String> {
+    let contents = fs::read_to_string(cache_path(kind, key)?).ok()?;
+    Some(contents.trim().to_string())
+}
+
+/// Save a value for [`read_cache`]. Failing to do so isn't fatal.
+fn write_cache(kind: &str, key: &str, value: &str) {
+    let Some(path) = cache_path(kind, key) else {
+        return;
+    };
+    if let Err(e) = path
+        .parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|()| fs::write(&path, value))
+    {
+        log::debug!("couldn't write {}: {}", path.display(), e);
+    }
+}
+
 const SPLITS: [(Event, RangeInclusive<u32>); 8] = [
     (Event::Verdigris, 3006..=3011),
     (Event::Vermilion, 3060..=3065),
@@ -63,16 +294,21 @@ const SPLITS: [(Event, RangeInclusive<u32>); 8] = [
 
 #[derive(Debug)]
 pub(crate) struct Game {
+    pid: Pid,
     handle: DebugIgnore<imp::Handle>,
     old: State,
     cur: State,
 }
 
-#[derive(Debug, Clone, PartialEq)]
-struct State {
-    room: (u32, u32),
+#[allow(clippy::struct_field_names)] // `state` is what VVVVVV calls it
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub(crate) struct State {
+    pub(crate) room: (u32, u32),
     gamestate: u32,
     state: u32,
+    /// how many times the player has died this run, if we know where the game keeps that
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) deaths: Option<u32>,
 }
 
 impl State {
@@ -81,17 +317,57 @@ impl State {
             room: (u32::MAX, u32::MAX),
             gamestate: u32::MAX,
             state: u32::MAX,
+            deaths: None,
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub(crate) struct Update {
     pub(crate) time: Duration,
+    pub(crate) state: State,
     pub(crate) event: Option<Event>,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[cfg(test)]
+impl Update {
+    /// An update from the middle of a run, for testing the things that handle updates.
+    pub(crate) fn test(
+        time: Duration,
+        room: (u32, u32),
+        deaths: u32,
+        event: Option<Event>,
+    ) -> Self {
+        Self {
+            time,
+            state: State {
+                room,
+                gamestate: 0,
+                state: 0,
+                deaths: Some(deaths),
+            },
+            event,
+        }
+    }
+}
+
+/// Format a game time like a timer does: `M:SS.CC`, or `H:MM:SS.CC` from an hour on.
+pub(crate) fn format_time(time: Duration) -> String {
+    let seconds = time.as_secs();
+    let centiseconds = time.subsec_millis() / 10;
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    if hours > 0 {
+        format!(
+            "{}:{:02}:{:02}.{:02}",
+            hours, minutes, seconds, centiseconds
+        )
+    } else {
+        format!("{}:{:02}.{:02}", minutes, seconds, centiseconds)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub(crate) enum Event {
     NewGame,
     Verdigris,
@@ -103,6 +379,47 @@ pub(crate) enum Event {
     IntermissionTwo,
     GameComplete,
     Reset,
+    /// the VVVVVV process we were attached to went away
+    Detached,
+    /// we (re-)attached to a VVVVVV process
+    Attached,
+}
+
+impl Event {
+    /// Whether this event ends a segment of the run.
+    pub(crate) fn is_split(self) -> bool {
+        matches!(
+            self,
+            Event::Verdigris
+                | Event::Vermilion
+                | Event::Victoria
+                | Event::Violet
+                | Event::Vitellary
+                | Event::IntermissionOne
+                | Event::IntermissionTwo
+                | Event::GameComplete
+        )
+    }
+}
+
+/// What to call an event where people will see it, e.g. "Intermission 1".
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Event::NewGame => "New game",
+            Event::Verdigris => "Verdigris",
+            Event::Vermilion => "Vermilion",
+            Event::Victoria => "Victoria",
+            Event::Violet => "Violet",
+            Event::Vitellary => "Vitellary",
+            Event::IntermissionOne => "Intermission 1",
+            Event::IntermissionTwo => "Intermission 2",
+            Event::GameComplete => "Game complete",
+            Event::Reset => "Reset",
+            Event::Detached => "Detached",
+            Event::Attached => "Attached",
+        })
+    }
 }
 
 impl Game {
@@ -110,16 +427,41 @@ impl Game {
         let handle = imp::find_game_object(pid)?;
         log::info!("attached to pid {}", pid);
         Ok(Game {
+            pid,
             handle: DebugIgnore(handle),
             old: State::new(),
             cur: State::new(),
         })
     }
 
+    /// Attach to a new process, keeping the splitter state from the old one.
+    ///
+    /// If a run was in progress when the old process went away, the first update from the new
+    /// process will see the game leave its playing state and report [`Event::Reset`].
+    pub(crate) fn reattach(&mut self, pid: Pid) -> Result<()> {
+        self.handle = DebugIgnore(imp::find_game_object(pid)?);
+        self.pid = pid;
+        log::info!("re-attached to pid {}", pid);
+        Ok(())
+    }
+
+    /// Make sure the game object can be read with `revision` before relying on it.
+    pub(crate) fn check(&self, revision: &Revision) -> Result<()> {
+        imp::read_game_object(&self.handle, revision).map(drop)
+    }
+
+    pub(crate) fn pid(&self) -> Pid {
+        self.pid
+    }
+
+    pub(crate) fn is_running(&self) -> bool {
+        imp::is_running(self.pid)
+    }
+
     pub(crate) fn update(&mut self, revision: &Revision) -> Result<Update> {
         let (state, time) = imp::read_game_object(&self.handle, revision)?;
         if self.old.state == u32::MAX {
-            self.old = state.clone();
+            self.old = state;
             self.cur = state;
         } else {
             self.old = std::mem::replace(&mut self.cur, state);
@@ -149,12 +491,21 @@ impl Game {
                 time
             );
         }
+        if self.old.deaths != self.cur.deaths {
+            log::debug!(
+                "deaths: {:?} -> {:?} @ {:?}",
+                self.old.deaths,
+                self.cur.deaths,
+                time
+            );
+        }
 
         if revision.is_playing_state(self.cur.gamestate)
             && !revision.is_playing_state(self.old.gamestate)
         {
             return Ok(Update {
                 time: Duration::ZERO,
+                state: self.cur,
                 event: Some(Event::NewGame),
             });
         }
@@ -163,10 +514,11 @@ impl Game {
         {
             return Ok(Update {
                 time,
+                state: self.cur,
                 event: Some(Event::Reset),
             });
         }
-        
+
         // `state` increments to 3006 prior to the switch case that jumps to the correct state. This
         // can cause `Event::Verdigris` to fire one cycle before the correct event. Check we're in
         // the right room ("Murdering Twinmaker" @ (115, 100)) or (Untitled @ (113, 102)) (telejump)
@@ -183,6 +535,10 @@ impl Game {
                 })
             };
 
-        Ok(Update { time, event })
+        Ok(Update {
+            time,
+            state: self.cur,
+            event,
+        })
     }
 }
@@ -0,0 +1,122 @@
+//! Adjusting a revision's layout from the command line, for builds that aren't in the table yet.
+
+use crate::game::Revision;
+use anyhow::{anyhow, Result};
+use std::str::FromStr;
+
+/// A field of the game object whose offset can be overridden.
+#[derive(Debug, Clone, Copy)]
+enum Field {
+    RoomX,
+    RoomY,
+    State,
+    Gamestate,
+    Timer,
+    Deaths,
+    Savetime,
+}
+
+impl FromStr for Field {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "room_x" => Field::RoomX,
+            "room_y" => Field::RoomY,
+            "state" => Field::State,
+            "gamestate" => Field::Gamestate,
+            "timer" => Field::Timer,
+            "deaths" => Field::Deaths,
+            "savetime" => Field::Savetime,
+            _ => {
+                return Err(format!(
+                    "unknown field {s:?} (expected room_x, room_y, state, gamestate, timer, deaths or savetime)"
+                ))
+            }
+        })
+    }
+}
+
+/// `FIELD=OFFSET`, e.g. `gamestate=88`
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct OffsetOverride {
+    field: Field,
+    offset: usize,
+}
+
+impl FromStr for OffsetOverride {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (field, offset) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected FIELD=OFFSET, got {s:?}"))?;
+        Ok(Self {
+            field: field.parse()?,
+            offset: offset
+                .parse()
+                .map_err(|e| format!("bad offset {offset:?}: {e}"))?,
+        })
+    }
+}
+
+/// The five `gamestate` values that mean the player is in a run, e.g. `0,4,5,6,7`
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct PlayingStates([u32; 5]);
+
+impl FromStr for PlayingStates {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let states = s
+            .split(',')
+            .map(|state| state.trim().parse())
+            .collect::<Result<Vec<u32>, _>>()
+            .map_err(|e| format!("bad playing states {s:?}: {e}"))?;
+        Ok(Self(states.try_into().map_err(|states: Vec<_>| {
+            format!("expected 5 playing states, got {}", states.len())
+        })?))
+    }
+}
+
+/// Changes to apply to whichever revision gets selected.
+#[derive(Debug, Default)]
+pub(crate) struct Overrides {
+    pub(crate) game_object_size: Option<usize>,
+    pub(crate) offsets: Vec<OffsetOverride>,
+    pub(crate) playing_states: Option<PlayingStates>,
+}
+
+impl Overrides {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.game_object_size.is_none() && self.offsets.is_empty() && self.playing_states.is_none()
+    }
+}
+
+impl Revision {
+    /// Get a copy of this revision with `overrides` applied.
+    pub(crate) fn with_overrides(&self, overrides: &Overrides) -> Result<Revision> {
+        let mut revision = self.clone();
+        if let Some(size) = overrides.game_object_size {
+            revision.game_object_size = size;
+        }
+        for &OffsetOverride { field, offset } in &overrides.offsets {
+            match field {
+                Field::RoomX => revision.room_x_offset = offset,
+                Field::RoomY => revision.room_y_offset = offset,
+                Field::State => revision.state_offset = offset,
+                Field::Gamestate => revision.gamestate_offset = offset,
+                Field::Timer => revision.timer_offset = offset,
+                Field::Deaths => revision.deaths_offset = Some(offset),
+                Field::Savetime => revision.savetime_offset = Some(offset),
+            }
+        }
+        if let Some(PlayingStates(states)) = overrides.playing_states {
+            revision.playing_states = states;
+        }
+        revision
+            .validate()
+            .map_err(|e| anyhow!("bad layout overrides: {e}"))?;
+        Ok(revision)
+    }
+}
@@ -0,0 +1,110 @@
+//! Loading the revision table from the `revisions.json` that `parse_vvvvvv_src` writes, so that
+//! newer VVVVVV commits can be supported without recompiling vitellary.
+
+use crate::game::{Commit, Revision, Table};
+use anyhow::{anyhow, bail, Result};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/// the version of the format we understand (`REVISIONS_FILE_VERSION` in `parse_vvvvvv_src`)
+const VERSION: u32 = 3;
+
+#[derive(Deserialize)]
+struct Version {
+    version: u32,
+}
+
+#[derive(Deserialize)]
+struct File {
+    layouts: Vec<Layout>,
+    tags: HashMap<String, u8>,
+    /// (commit ID, layout index), newest first
+    commits: Vec<(String, u8)>,
+}
+
+#[derive(Deserialize)]
+struct Layout {
+    game_object_size: usize,
+    playing_states: [u32; 5],
+    offsets: Offsets,
+}
+
+#[derive(Deserialize)]
+struct Offsets {
+    room_x: usize,
+    room_y: usize,
+    state: usize,
+    gamestate: usize,
+    timer: usize,
+    deaths: usize,
+    savetime: usize,
+}
+
+/// Load a revision table from `path`.
+///
+/// The table lives for the rest of the program, so everything in it is leaked to get the same
+/// `'static` data as the table compiled into vitellary.
+pub(super) fn load(path: &Path) -> Result<Table> {
+    let contents = fs::read_to_string(path)?;
+    // check this first, so that a file in a newer format gets a useful error
+    let Version { version } = serde_json::from_str(&contents)?;
+    if version != VERSION {
+        bail!("unsupported revisions file version {version} (expected {VERSION})");
+    }
+    let file: File = serde_json::from_str(&contents)?;
+
+    let layouts = file
+        .layouts
+        .into_iter()
+        .map(|layout| {
+            let revision = Revision {
+                game_object_size: layout.game_object_size,
+                room_x_offset: layout.offsets.room_x,
+                room_y_offset: layout.offsets.room_y,
+                state_offset: layout.offsets.state,
+                gamestate_offset: layout.offsets.gamestate,
+                timer_offset: layout.offsets.timer,
+                deaths_offset: Some(layout.offsets.deaths),
+                savetime_offset: Some(layout.offsets.savetime),
+                playing_states: layout.playing_states,
+            };
+            revision.validate()?;
+            Ok(revision)
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let check_layout = |layout: u8| {
+        if usize::from(layout) < layouts.len() {
+            Ok(layout)
+        } else {
+            Err(anyhow!("no layout with index {layout}"))
+        }
+    };
+
+    let mut tags = file
+        .tags
+        .into_iter()
+        .map(|(name, layout)| Ok((&*name.leak(), check_layout(layout)?)))
+        .collect::<Result<Vec<_>>>()?;
+    tags.sort_unstable();
+    let mut commits = file
+        .commits
+        .into_iter()
+        .enumerate()
+        .map(|(order, (id, layout))| {
+            Ok(Commit {
+                id: id.to_ascii_lowercase().leak(),
+                layout: check_layout(layout)?,
+                order: u16::try_from(order)?,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    commits.sort_unstable_by_key(|commit| commit.id);
+
+    Ok(Table {
+        layouts: layouts.leak(),
+        tags: tags.leak(),
+        commits: commits.leak(),
+    })
+}
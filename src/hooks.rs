@@ -0,0 +1,108 @@
+//! Running the user's own commands when things happen in the game, e.g. to switch scenes or play a
+//! sound.
+//!
+//! Commands are run with `sh -c`, with these environment variables set:
+//!
+//! - `VITELLARY_EVENT`: what happened, e.g. `NewGame`, `Violet` or `Reset`
+//! - `VITELLARY_TIME`: the game time, in seconds
+//! - `VITELLARY_ROOM_X` and `VITELLARY_ROOM_Y`: the room the player is in
+//! - `VITELLARY_PID`: the VVVVVV process ID
+
+use crate::game::{Event, Update};
+use crate::hub::Hub;
+use crate::sink::Sink;
+use anyhow::Result;
+use read_process_memory::Pid;
+use std::process::Command;
+use std::sync::Arc;
+
+/// The commands to run for each kind of event.
+pub(crate) struct Hooks {
+    pub(crate) start: Vec<String>,
+    pub(crate) split: Vec<String>,
+    pub(crate) reset: Vec<String>,
+    /// run for every event, including the ones above
+    pub(crate) event: Vec<String>,
+}
+
+impl Hooks {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.start.is_empty()
+            && self.split.is_empty()
+            && self.reset.is_empty()
+            && self.event.is_empty()
+    }
+
+    /// A sink that runs the hooks, looking up which process they're for in `hub`.
+    pub(crate) fn into_sink(self, hub: &Arc<Hub>) -> Runner {
+        Runner {
+            hooks: self,
+            hub: Arc::clone(hub),
+            pid: None,
+        }
+    }
+}
+
+pub(crate) struct Runner {
+    hooks: Hooks,
+    hub: Arc<Hub>,
+    /// the last process we were attached to, which is still the one we've just detached from
+    pid: Option<Pid>,
+}
+
+impl Runner {
+    fn commands(&self, event: Event) -> impl Iterator<Item = &String> {
+        let specific: &[String] = match event {
+            Event::NewGame => &self.hooks.start,
+            Event::Reset => &self.hooks.reset,
+            event if event.is_split() => &self.hooks.split,
+            _ => &[],
+        };
+        specific.iter().chain(&self.hooks.event)
+    }
+
+    fn run(&self, command: &str, event: Event, update: &Update) {
+        let mut process = Command::new("sh");
+        process
+            .args(["-c", command])
+            .env("VITELLARY_EVENT", format!("{:?}", event))
+            .env("VITELLARY_TIME", update.time.as_secs_f64().to_string())
+            .env("VITELLARY_ROOM_X", update.state.room.0.to_string())
+            .env("VITELLARY_ROOM_Y", update.state.room.1.to_string());
+        if let Some(pid) = self.pid {
+            process.env("VITELLARY_PID", pid.to_string());
+        }
+        log::debug!("running {:?} for {:?}", command, event);
+        match process.spawn() {
+            // wait for it elsewhere, so that a slow command doesn't hold up the next event
+            Ok(mut child) => {
+                let command = command.to_owned();
+                std::thread::spawn(move || match child.wait() {
+                    Ok(status) if status.success() => {}
+                    Ok(status) => log::warn!("hook {:?} failed with {}", command, status),
+                    Err(e) => log::warn!("failed to wait for hook {:?}: {}", command, e),
+                });
+            }
+            Err(e) => log::warn!("failed to run hook {:?}: {}", command, e),
+        }
+    }
+}
+
+impl Sink for Runner {
+    fn name(&self) -> String {
+        "hooks".into()
+    }
+
+    fn update(&mut self, update: &Update) -> Result<()> {
+        let Some(event) = update.event else {
+            return Ok(());
+        };
+        if let Some(attached) = self.hub.snapshot().attached {
+            self.pid = Some(attached.pid);
+        }
+        for command in self.commands(event) {
+            self.run(command, event, update);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,189 @@
+//! A small HTTP API, on the same address as the WebSocket server, for things that would rather
+//! not speak WebSocket:
+//!
+//! - `GET /state`: the latest game time and state, the splits in the current run, and which
+//!   process and revision we're attached to
+//! - `GET /events`: a stream of events (splits etc.), as server-sent events
+//! - `GET /health`: whether vitellary is running, and attached to VVVVVV
+//! - `GET /overlay`: a page showing the run, for an OBS browser source
+
+use crate::game::Event;
+use crate::hub::Hub;
+use crate::server::Stream;
+use crate::stream;
+use anyhow::{bail, Result};
+use read_process_memory::Pid;
+use serde::Serialize;
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+/// the most we'll read of a request before giving up on it
+const MAX_HEADERS_SIZE: usize = 8192;
+/// how long a client has to send us its request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+/// the page for `GET /overlay`
+const OVERLAY: &str = include_str!("overlay.html");
+/// how often to send something on an event stream, so that we notice when the client goes away
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+/// The parts of a request we care about.
+pub(crate) struct Request {
+    method: String,
+    path: String,
+    /// whether it's a WebSocket handshake
+    pub(crate) websocket: bool,
+}
+
+/// Read a request's headers from `stream`. Also returns everything that was read, for the
+/// WebSocket handshake to look at again.
+pub(crate) fn read_request(stream: &mut impl Stream) -> Result<(Request, Vec<u8>)> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    let mut read = vec![];
+    let mut buf = [0; 1024];
+    let end = loop {
+        if let Some(end) = read.windows(4).position(|window| window == b"\r\n\r\n") {
+            break end;
+        }
+        if read.len() > MAX_HEADERS_SIZE {
+            bail!("request headers are too long");
+        }
+        let len = stream.read(&mut buf)?;
+        if len == 0 {
+            bail!("connection closed before sending a request");
+        }
+        read.extend_from_slice(&buf[..len]);
+    };
+    let headers = String::from_utf8_lossy(&read[..end]);
+    let mut lines = headers.lines();
+    let mut parts = lines.next().unwrap_or_default().split_whitespace();
+    let (method, path) = (
+        parts.next().unwrap_or_default().to_owned(),
+        parts.next().unwrap_or_default().to_owned(),
+    );
+    let websocket = lines.any(|line| {
+        line.split_once(':').is_some_and(|(name, value)| {
+            name.trim().eq_ignore_ascii_case("upgrade")
+                && value.trim().eq_ignore_ascii_case("websocket")
+        })
+    });
+    Ok((
+        Request {
+            method,
+            path,
+            websocket,
+        },
+        read,
+    ))
+}
+
+/// The response to `GET /state`.
+#[derive(Serialize)]
+struct Status {
+    attached: bool,
+    pid: Option<Pid>,
+    revision: Option<String>,
+    /// game time, in seconds
+    time: Option<f64>,
+    state: Option<crate::game::State>,
+    /// the splits in the current run so far
+    splits: Vec<Split>,
+}
+
+#[derive(Serialize)]
+struct Split {
+    event: Event,
+    /// game time, in seconds
+    time: f64,
+}
+
+fn respond(stream: &mut impl Write, status: &str, content_type: &str, body: &str) -> Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\n\
+         Content-Type: {}\r\n\
+         Content-Length: {}\r\n\
+         Access-Control-Allow-Origin: *\r\n\
+         Connection: close\r\n\
+         \r\n\
+         {}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )?;
+    Ok(())
+}
+
+/// Answer one HTTP request.
+pub(crate) fn handle(request: &Request, mut stream: impl Write, hub: &Hub) -> Result<()> {
+    log::debug!("{} {}", request.method, request.path);
+    if request.method != "GET" {
+        return respond(&mut stream, "405 Method Not Allowed", "text/plain", "");
+    }
+    // ignore any query string
+    match request.path.split('?').next().unwrap_or_default() {
+        "/health" => {
+            let attached = hub.snapshot().attached.is_some();
+            let body = serde_json::json!({ "status": "ok", "attached": attached });
+            respond(&mut stream, "200 OK", "application/json", &body.to_string())
+        }
+        "/state" => {
+            let snapshot = hub.snapshot();
+            let body = serde_json::to_string(&Status {
+                attached: snapshot.attached.is_some(),
+                pid: snapshot.attached.as_ref().map(|attached| attached.pid),
+                revision: snapshot.attached.map(|attached| attached.revision),
+                time: snapshot.latest.map(|latest| latest.time.as_secs_f64()),
+                state: snapshot.latest.map(|latest| latest.state),
+                splits: snapshot
+                    .run
+                    .iter()
+                    .filter_map(|update| {
+                        let event = update.event.filter(|event| event.is_split())?;
+                        Some(Split {
+                            event,
+                            time: update.time.as_secs_f64(),
+                        })
+                    })
+                    .collect(),
+            })?;
+            respond(&mut stream, "200 OK", "application/json", &body)
+        }
+        "/events" => send_events(stream, hub),
+        "/overlay" => respond(&mut stream, "200 OK", "text/html; charset=utf-8", OVERLAY),
+        _ => respond(&mut stream, "404 Not Found", "text/plain", "not found\n"),
+    }
+}
+
+/// Send events to the client as they happen, until it goes away.
+fn send_events(mut stream: impl Write, hub: &Hub) -> Result<()> {
+    let updates = hub.subscribe();
+    stream.write_all(
+        b"HTTP/1.1 200 OK\r\n\
+          Content-Type: text/event-stream\r\n\
+          Cache-Control: no-cache\r\n\
+          Access-Control-Allow-Origin: *\r\n\
+          Connection: close\r\n\
+          \r\n",
+    )?;
+    let mut last_write = Instant::now();
+    loop {
+        match updates.recv_timeout(KEEPALIVE_INTERVAL) {
+            Some(update) if update.event.is_some() => {
+                let name = serde_json::to_value(update.event)?;
+                write!(
+                    stream,
+                    "event: {}\ndata: {}\n\n",
+                    name.as_str().unwrap_or_default(),
+                    stream::encode(&update, None)
+                )?;
+                last_write = Instant::now();
+            }
+            _ if last_write.elapsed() >= KEEPALIVE_INTERVAL => {
+                stream.write_all(b": keepalive\n\n")?;
+                last_write = Instant::now();
+            }
+            _ => {}
+        }
+    }
+}
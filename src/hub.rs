@@ -0,0 +1,175 @@
+//! Sending every update to every connected client.
+//!
+//! Each client has its own queue. Events (starts, splits, resets...) are queued until the client
+//! takes them, so a slow client never misses one; the game time and state only matter while
+//! they're current, so a client that falls behind just gets the latest ones.
+
+use crate::game::{Event, Update};
+use read_process_memory::Pid;
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex, Weak};
+use std::time::Duration;
+
+#[derive(Default)]
+struct Pending {
+    /// events that haven't been sent yet, with the game time they happened at
+    events: VecDeque<Update>,
+    /// the latest update without an event, if it's newer than every event in `events`
+    latest: Option<Update>,
+}
+
+#[derive(Default)]
+struct Queue {
+    pending: Mutex<Pending>,
+    ready: Condvar,
+}
+
+impl Queue {
+    fn push(&self, update: Update) {
+        let mut pending = self.pending.lock().unwrap();
+        if update.event.is_some() {
+            pending.events.push_back(update);
+            pending.latest = None;
+        } else {
+            pending.latest = Some(update);
+        }
+        self.ready.notify_one();
+    }
+}
+
+/// A client's end of the hub.
+///
+/// Dropping it removes the client from the hub.
+pub(crate) struct Subscription {
+    queue: Arc<Queue>,
+    /// how many splits the run in progress had when the client connected, if they weren't replayed
+    pub(crate) missed_splits: usize,
+}
+
+impl Subscription {
+    /// Wait up to `timeout` for the next update: the oldest event that hasn't been sent yet, or
+    /// else the latest game time and state.
+    pub(crate) fn recv_timeout(&self, timeout: Duration) -> Option<Update> {
+        let mut pending = self.queue.pending.lock().unwrap();
+        if pending.events.is_empty() && pending.latest.is_none() {
+            pending = self.queue.ready.wait_timeout(pending, timeout).unwrap().0;
+        }
+        pending.events.pop_front().or_else(|| pending.latest.take())
+    }
+}
+
+struct Clients {
+    queues: Vec<Weak<Queue>>,
+    /// the events since the current run started
+    run: Vec<Update>,
+    /// whether to send `run` to new clients
+    replay: bool,
+    /// the latest update
+    latest: Option<Update>,
+    attached: Option<Attached>,
+}
+
+/// The process we're attached to.
+#[derive(Debug, Clone)]
+pub(crate) struct Attached {
+    pub(crate) pid: Pid,
+    /// the revision of VVVVVV it's running
+    pub(crate) revision: String,
+}
+
+/// What we know right now.
+pub(crate) struct Snapshot {
+    pub(crate) latest: Option<Update>,
+    pub(crate) attached: Option<Attached>,
+    /// the events since the current run started
+    pub(crate) run: Vec<Update>,
+}
+
+/// The queues of every connected client.
+pub(crate) struct Hub {
+    clients: Mutex<Clients>,
+}
+
+impl Hub {
+    /// If `replay` is set, clients that connect in the middle of a run are sent its events so far.
+    pub(crate) fn new(replay: bool) -> Self {
+        Self {
+            clients: Mutex::new(Clients {
+                queues: vec![],
+                run: vec![],
+                replay,
+                latest: None,
+                attached: None,
+            }),
+        }
+    }
+
+    /// Start a queue for a new client, which will get every update from now on.
+    pub(crate) fn subscribe(&self) -> Subscription {
+        let queue = Arc::new(Queue::default());
+        let mut clients = self.clients.lock().unwrap();
+        let missed_splits = if clients.replay {
+            for update in &clients.run {
+                queue.push(*update);
+            }
+            0
+        } else {
+            clients
+                .run
+                .iter()
+                .filter(|update| update.event.is_some_and(Event::is_split))
+                .count()
+        };
+        if let Some(latest) = clients.latest {
+            queue.push(Update {
+                event: None,
+                ..latest
+            });
+        }
+        clients.queues.push(Arc::downgrade(&queue));
+        Subscription {
+            queue,
+            missed_splits,
+        }
+    }
+
+    pub(crate) fn attached(&self, pid: Pid, revision: &str) {
+        self.clients.lock().unwrap().attached = Some(Attached {
+            pid,
+            revision: revision.into(),
+        });
+    }
+
+    pub(crate) fn detached(&self) {
+        self.clients.lock().unwrap().attached = None;
+    }
+
+    pub(crate) fn snapshot(&self) -> Snapshot {
+        let clients = self.clients.lock().unwrap();
+        Snapshot {
+            latest: clients.latest,
+            attached: clients.attached.clone(),
+            run: clients.run.clone(),
+        }
+    }
+
+    /// Queue `update` for every client.
+    pub(crate) fn broadcast(&self, update: Update) {
+        let mut clients = self.clients.lock().unwrap();
+        clients.latest = Some(update);
+        match update.event {
+            Some(Event::NewGame) => clients.run = vec![update],
+            Some(Event::Reset) => clients.run.clear(),
+            // only worth keeping during a run
+            Some(_) if !clients.run.is_empty() => clients.run.push(update),
+            _ => {}
+        }
+        clients.queues.retain(|queue| match queue.upgrade() {
+            Some(queue) => {
+                queue.push(update);
+                true
+            }
+            None => false,
+        });
+    }
+}
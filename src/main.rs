@@ -1,10 +1,41 @@
 #![warn(clippy::pedantic)]
 #![allow(clippy::assertions_on_constants, clippy::uninlined_format_args)]
 
+mod connect;
+mod connection;
+mod discord;
 mod game;
+mod hooks;
+mod http;
+mod hub;
+mod obs;
+mod osc;
+mod output;
+mod protocol;
+mod reconnect;
+mod server;
+mod show_revisions;
+mod sink;
+mod splits;
+mod stream;
+mod text_files;
+mod webhook;
 
-use crate::game::{Game, Revision, Update};
-use anyhow::anyhow;
+use crate::connect::Endpoint;
+use crate::discord::Discord;
+use crate::game::{Confidence, Game, OffsetOverride, Overrides, PlayingStates, Revision, Update};
+use crate::hooks::Hooks;
+use crate::hub::Hub;
+use crate::obs::{Obs, SceneSwitch, Trigger};
+use crate::osc::Osc;
+use crate::output::Output;
+use crate::protocol::Protocol;
+use crate::server::Server;
+use crate::show_revisions::RevisionsCommand;
+use crate::sink::Registry;
+use crate::splits::Splits;
+use crate::text_files::TextFiles;
+use crate::webhook::Webhook;
 use anyhow::{bail, Context, Result};
 use argh::FromArgs;
 use env_logger::Env;
@@ -12,9 +43,13 @@ use game::Event;
 use read_process_memory::Pid;
 use std::io::BufRead;
 use std::net::{SocketAddr, TcpListener};
+use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::sync::Arc;
 use std::time::Duration;
-use tungstenite::Message;
+
+/// how often to look for a VVVVVV process while we're not attached to one
+const ATTACH_INTERVAL: Duration = Duration::from_secs(1);
 
 #[allow(clippy::doc_markdown)] // lol
 #[derive(FromArgs)]
@@ -24,23 +59,141 @@ struct Args {
     #[argh(switch, short = 'v')]
     verbose: bool,
 
-    /// bind address for WebSocket (default: 127.0.0.1:5555)
+    /// bind address for WebSocket and HTTP (default: 127.0.0.1:5555)
     #[argh(option)]
     bind: Option<SocketAddr>,
 
-    /// which revision of VVVVVV you have
+    /// also serve WebSocket and HTTP on a Unix socket that only you can connect to, e.g. $XDG_RUNTIME_DIR/vitellary.sock
+    #[argh(option)]
+    unix: Option<PathBuf>,
+
+    /// the protocol to speak to timers: json, legacy for older versions of LiveSplit One, or livesplit for LiveSplit's server component (default: json)
     ///
-    /// this can be a version number (e.g. "2.3") or a commit ID (e.g. "48cddf57a67a90be0b6f6d8a780f766ca15942a7").
-    #[argh(option, default = "String::from(\"master\")")]
-    revision: String,
+    /// clients can also pick one by connecting to a path, e.g. ws://127.0.0.1:5555/legacy.
+    #[argh(option)]
+    protocol: Option<Protocol>,
+
+    /// also connect to LiveSplit's server component at tcp://HOST:PORT or ws://HOST:PORT/PATH (can be repeated)
+    #[argh(option)]
+    connect: Vec<Endpoint>,
+
+    /// record runs in a LiveSplit splits file (.lss), whether or not anything is connected
+    #[argh(option)]
+    splits: Option<PathBuf>,
+
+    /// also write updates to stdout or a file: ndjson or ndjson:PATH (can be repeated)
+    #[argh(option)]
+    output: Vec<Output>,
+
+    /// send OSC messages over UDP to HOST:PORT (can be repeated)
+    #[argh(option)]
+    osc: Vec<String>,
+
+    /// show the run in Discord, as the Discord application with this client ID
+    #[argh(option)]
+    discord: Option<String>,
+
+    /// keep a directory of text files (igt.txt, room.txt, last_split.txt, deaths.txt and attempts.txt) up to date, for OBS text sources
+    #[argh(option)]
+    text_dir: Option<PathBuf>,
+
+    /// POST each event as JSON to an http:// URL (can be repeated)
+    #[argh(option)]
+    webhook: Vec<Webhook>,
+
+    /// control OBS over obs-websocket at ws://HOST:PORT (usually ws://127.0.0.1:4455)
+    #[argh(option)]
+    obs: Option<String>,
+
+    /// the obs-websocket password (default: $VITELLARY_OBS_PASSWORD)
+    #[argh(option)]
+    obs_password: Option<String>,
+
+    /// switch OBS to a scene when something happens, e.g. "start=Gameplay" or "game_complete=Results" (can be repeated)
+    ///
+    /// events are start, split (every split), reset, or a split: verdigris, vermilion, victoria,
+    /// violet, vitellary, intermission_one, intermission_two or game_complete.
+    #[argh(option)]
+    obs_scene: Vec<SceneSwitch>,
+
+    /// save OBS's replay buffer when something happens, e.g. "game_complete" (can be repeated)
+    #[argh(option)]
+    obs_save_replay: Vec<Trigger>,
+
+    /// an OBS text source to show the name of the last split in
+    #[argh(option)]
+    obs_split_text: Option<String>,
+
+    /// an OBS text source to show the current area (or room, where the area is unknown) in
+    #[argh(option)]
+    obs_room_text: Option<String>,
+
+    /// run a shell command when a run starts (can be repeated)
+    ///
+    /// hooks get VITELLARY_EVENT, VITELLARY_TIME, VITELLARY_ROOM_X, VITELLARY_ROOM_Y and
+    /// VITELLARY_PID in their environment.
+    #[argh(option)]
+    on_start: Vec<String>,
+
+    /// run a shell command on each split (can be repeated)
+    #[argh(option)]
+    on_split: Vec<String>,
+
+    /// run a shell command when a run is reset (can be repeated)
+    #[argh(option)]
+    on_reset: Vec<String>,
+
+    /// run a shell command on every event, including attaching to and detaching from VVVVVV (can be repeated)
+    #[argh(option)]
+    on_event: Vec<String>,
+
+    /// send clients that connect in the middle of a run the splits they missed
+    #[argh(switch)]
+    replay: bool,
+
+    /// which revision of VVVVVV you have (default: detect it)
+    ///
+    /// this can be a version number (e.g. "2.3"), a commit ID (e.g. "48cddf57"), or the output of
+    /// `git describe`. `vitellary revisions` lists them.
+    #[argh(option)]
+    revision: Option<String>,
+
+    /// load revisions from a revisions.json written by parse_vvvvvv_src instead of using the built-in ones
+    #[argh(option)]
+    revisions_file: Option<PathBuf>,
+
+    /// override the offset of a field of the game object, e.g. "gamestate=88" (can be repeated)
+    ///
+    /// fields are room_x, room_y, state, gamestate, timer, deaths and savetime.
+    #[argh(option)]
+    offset: Vec<OffsetOverride>,
+
+    /// override the size of the game object
+    #[argh(option)]
+    object_size: Option<usize>,
+
+    /// override the gamestates that count as playing, e.g. "0,4,5,6,7"
+    #[argh(option)]
+    playing_states: Option<PlayingStates>,
+
+    #[argh(subcommand)]
+    command: Option<Subcommand>,
 
     /// process ID of a specific VVVVVV process
+    ///
+    /// if it exits, vitellary waits for the next VVVVVV process instead.
     #[argh(positional)]
     pid: Option<Pid>,
 }
 
+#[derive(FromArgs)]
+#[argh(subcommand)]
+enum Subcommand {
+    Revisions(RevisionsCommand),
+}
+
 fn main() -> Result<()> {
-    let args: Args = argh::from_env();
+    let mut args: Args = argh::from_env();
     env_logger::Builder::from_env(Env::default().default_filter_or(if args.verbose {
         "vitellary=debug"
     } else {
@@ -48,73 +201,264 @@ fn main() -> Result<()> {
     }))
     .init();
 
-    let revision = Revision::get(&args.revision).ok_or_else(|| anyhow!("no such revision"))?;
+    if let Some(path) = &args.revisions_file {
+        Revision::load_table(path)?;
+    }
+    if let Some(Subcommand::Revisions(command)) = &args.command {
+        return command.run();
+    }
+    let selector = RevisionSelector {
+        fixed: args
+            .revision
+            .as_deref()
+            .map(|name| {
+                let resolved = Revision::resolve(name)?;
+                anyhow::Ok((
+                    resolved.commit.unwrap_or(name).to_owned(),
+                    resolved.revision,
+                ))
+            })
+            .transpose()?,
+        overrides: Overrides {
+            game_object_size: args.object_size,
+            offsets: std::mem::take(&mut args.offset),
+            playing_states: args.playing_states,
+        },
+    };
+    // catch bad overrides now rather than every time we try to attach
+    if let Some((_, revision)) = &selector.fixed {
+        revision.with_overrides(&selector.overrides)?;
+    }
 
-    let pid = if let Some(pid) = args.pid {
-        pid
-    } else {
-        let output = Command::new("pgrep")
-            .args(["-n", "VVVVVV"])
-            .output()
-            .context("failed to run pgrep")?;
-        if output.status.success() {
+    let protocol = args.protocol.unwrap_or(Protocol::Json);
+    let hub = Arc::new(Hub::new(args.replay));
+    let mut sinks = sinks(&mut args, &hub)?;
+    listen(args.bind, args.unix.as_deref(), protocol, &mut sinks)?;
+    for endpoint in std::mem::take(&mut args.connect) {
+        sinks.add_source(endpoint);
+    }
+    sinks.spawn(&hub);
+
+    let (mut game, mut selected) = match args.pid {
+        Some(pid) => {
+            let game = Game::attach(pid)?;
+            let selected = selector.select(&game)?;
+            (game, selected)
+        }
+        None => wait_for_game(&selector)?,
+    };
+    hub.attached(game.pid(), &selected.name);
+
+    let mut latest = None;
+    loop {
+        match game.update(&selected.revision) {
+            Ok(update) => {
+                latest = Some(update);
+                hub.broadcast(update);
+            }
+            Err(e) => {
+                if game.is_running() {
+                    log::warn!("failed to read from pid {}: {:#}", game.pid(), e);
+                } else {
+                    log::warn!("VVVVVV (pid {}) exited", game.pid());
+                }
+                let event = |event| {
+                    latest.map(|latest| Update {
+                        event: Some(event),
+                        ..latest
+                    })
+                };
+                hub.detached();
+                if let Some(update) = event(Event::Detached) {
+                    hub.broadcast(update);
+                }
+                std::thread::sleep(ATTACH_INTERVAL);
+                selected = wait_for_reattach(&mut game, &selector)?;
+                hub.attached(game.pid(), &selected.name);
+                if let Some(update) = event(Event::Attached) {
+                    hub.broadcast(update);
+                }
+            }
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+/// Everything that handles updates by itself, without anything connecting to us.
+fn sinks(args: &mut Args, hub: &Arc<Hub>) -> Result<Registry> {
+    let mut sinks = Registry::default();
+    if let Some(path) = &args.splits {
+        sinks.add(Splits::load(path)?);
+    }
+    for output in &args.output {
+        sinks.add(output.open()?);
+    }
+    for target in std::mem::take(&mut args.osc) {
+        sinks.add(Osc::open(target)?);
+    }
+    if let Some(client_id) = args.discord.take() {
+        sinks.add(Discord::new(client_id));
+    }
+    if let Some(dir) = args.text_dir.take() {
+        sinks.add(TextFiles::open(dir)?);
+    }
+    for webhook in std::mem::take(&mut args.webhook) {
+        sinks.add(webhook);
+    }
+    let hooks = Hooks {
+        start: std::mem::take(&mut args.on_start),
+        split: std::mem::take(&mut args.on_split),
+        reset: std::mem::take(&mut args.on_reset),
+        event: std::mem::take(&mut args.on_event),
+    };
+    if !hooks.is_empty() {
+        sinks.add(hooks.into_sink(hub));
+    }
+    if let Some(url) = args.obs.take() {
+        let password = args
+            .obs_password
+            .take()
+            .or_else(|| std::env::var("VITELLARY_OBS_PASSWORD").ok());
+        let actions = obs::Actions {
+            scenes: std::mem::take(&mut args.obs_scene),
+            save_replay: std::mem::take(&mut args.obs_save_replay),
+            split_text: args.obs_split_text.take(),
+            room_text: args.obs_room_text.take(),
+        };
+        sinks.add(Obs::new(url, password, actions));
+    }
+    Ok(sinks)
+}
+
+/// Add the WebSocket and HTTP server on `bind` (default: 127.0.0.1:5555), and on the Unix socket
+/// at `unix` if there is one, to `sinks`.
+fn listen(
+    bind: Option<SocketAddr>,
+    unix: Option<&Path>,
+    protocol: Protocol,
+    sinks: &mut Registry,
+) -> Result<()> {
+    let bind = bind.unwrap_or_else(|| ([127, 0, 0, 1], 5555).into());
+    let listener = TcpListener::bind(bind).context("failed to bind WebSocket address")?;
+    log::info!("listening on ws://{} and http://{}", bind, bind);
+    sinks.add_source(Server::new(listener, protocol));
+    if let Some(path) = unix {
+        let listener = server::bind_unix(path)?;
+        log::info!("listening on {}", path.display());
+        sinks.add_source(Server::new(listener, protocol));
+    }
+    Ok(())
+}
+
+/// Find the most recently started VVVVVV process, if there is one.
+fn find_pid() -> Result<Option<Pid>> {
+    let output = Command::new("pgrep")
+        .args(["-n", "VVVVVV"])
+        .output()
+        .context("failed to run pgrep")?;
+    if output.status.success() {
+        Ok(Some(
             output
                 .stdout
                 .lines()
                 .next()
                 .expect("pgrep returned 0 with no output")
                 .expect("pgrep output invalid UTF-8")
-                .parse()?
-        } else if output.status.code() == Some(1) {
-            bail!("no VVVVVV process found");
-        } else {
-            bail!("pgrep failed with {}", output.status);
-        }
-    };
+                .parse()?,
+        ))
+    } else if output.status.code() == Some(1) {
+        Ok(None)
+    } else {
+        bail!("pgrep failed with {}", output.status);
+    }
+}
 
-    let mut game = Game::attach(pid)?;
-    let (sender, receiver) = crossbeam_channel::bounded::<Update>(10);
-
-    let bind = args.bind.unwrap_or_else(|| ([127, 0, 0, 1], 5555).into());
-    let server = TcpListener::bind(bind).context("failed to bind WebSocket address")?;
-    log::info!("listening on ws://{}", bind);
-    std::thread::spawn(move || {
-        let receiver = receiver;
-        for stream in server.incoming() {
-            let receiver = receiver.clone();
-            std::thread::spawn(move || -> Result<()> {
-                let mut websocket = tungstenite::accept(stream.unwrap())?;
-                loop {
-                    let update = receiver.recv()?;
-                    websocket.write_message(Message::Text(format!(
-                        "setgametime {}.{:02}",
-                        update.time.as_secs(),
-                        update.time.subsec_nanos() / 10_000_000
-                    )))?;
-                    if let Some(event) = update.event {
-                        websocket.write_message(Message::Text(
-                            match event {
-                                Event::NewGame => "start",
-                                Event::Verdigris
-                                | Event::Vermilion
-                                | Event::Victoria
-                                | Event::Violet
-                                | Event::Vitellary
-                                | Event::IntermissionOne
-                                | Event::IntermissionTwo
-                                | Event::GameComplete => "split",
-                                Event::Reset => "reset",
-                            }
-                            .into(),
-                        ))?;
-                    }
+/// Call `attach` with each VVVVVV process we find until it succeeds.
+///
+/// Attaching can fail for a little while after the game starts (e.g. before the game object has
+/// been initialized), so we keep retrying the same process rather than giving up on it.
+fn wait_for<T>(mut attach: impl FnMut(Pid) -> Result<T>) -> Result<T> {
+    let mut waiting = false;
+    let mut failed = None;
+    loop {
+        if let Some(pid) = find_pid()? {
+            match attach(pid) {
+                Ok(t) => return Ok(t),
+                Err(e) if failed != Some(pid) => {
+                    log::warn!("failed to attach to pid {}: {:#}; retrying", pid, e);
+                    failed = Some(pid);
                 }
-            });
+                Err(e) => log::debug!("failed to attach to pid {}: {:#}", pid, e),
+            }
+        } else if !waiting {
+            log::info!("waiting for VVVVVV to start");
+            waiting = true;
         }
-    });
+        std::thread::sleep(ATTACH_INTERVAL);
+    }
+}
 
-    loop {
-        sender.try_send(game.update(revision)?).ok();
-        std::thread::sleep(Duration::from_millis(10));
+fn wait_for_game(selector: &RevisionSelector) -> Result<(Game, Selected)> {
+    wait_for(|pid| {
+        let game = Game::attach(pid)?;
+        let selected = selector.select(&game)?;
+        Ok((game, selected))
+    })
+}
+
+fn wait_for_reattach(game: &mut Game, selector: &RevisionSelector) -> Result<Selected> {
+    wait_for(|pid| {
+        game.reattach(pid)?;
+        selector.select(game)
+    })
+}
+
+/// How to decide which revision we're attached to.
+struct RevisionSelector {
+    /// the revision from the command line, if any, with its name
+    fixed: Option<(String, &'static Revision)>,
+    overrides: Overrides,
+}
+
+/// The revision we're attached to, and what to call it.
+struct Selected {
+    name: String,
+    revision: Revision,
+}
+
+impl RevisionSelector {
+    /// Use the revision from the command line if there was one, otherwise detect it. Then apply
+    /// any overrides from the command line, and make sure the game object can be read with it.
+    fn select(&self, game: &Game) -> Result<Selected> {
+        let (name, revision) = if let Some((name, revision)) = &self.fixed {
+            (name.clone(), *revision)
+        } else {
+            let (name, revision, confidence) = game.detect_revision()?;
+            if confidence == Confidence::Low {
+                log::warn!(
+                    "guessed revision {} ({}); use --revision if this is wrong",
+                    name,
+                    confidence
+                );
+            } else {
+                log::info!("detected revision {} ({})", name, confidence);
+            }
+            (name.to_owned(), revision)
+        };
+        let selected = if self.overrides.is_empty() {
+            Selected {
+                name,
+                revision: revision.clone(),
+            }
+        } else {
+            let revision = revision.with_overrides(&self.overrides)?;
+            log::info!("using custom layout {:?}", revision);
+            Selected {
+                name: format!("{} (with overrides)", name),
+                revision,
+            }
+        };
+        game.check(&selected.revision)?;
+        Ok(selected)
     }
 }
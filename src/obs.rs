@@ -0,0 +1,403 @@
+//! Controlling OBS when things happen in the game, over obs-websocket (protocol version 5):
+//! switching scenes, saving the replay buffer, and keeping text sources up to date.
+
+use crate::game::{describe_room, Event, Update};
+use crate::reconnect::{Reconnect, Status};
+use crate::sink::Sink;
+use anyhow::{anyhow, bail, Context, Result};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::str::FromStr;
+use std::time::Duration;
+use tungstenite::{Message, WebSocket};
+
+/// the obs-websocket RPC version we speak
+const RPC_VERSION: u64 = 1;
+/// how long to wait for OBS to connect, or to answer
+const TIMEOUT: Duration = Duration::from_secs(5);
+/// how long to wait before trying to connect again
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(2);
+
+// obs-websocket opcodes
+const HELLO: u64 = 0;
+const IDENTIFY: u64 = 1;
+const IDENTIFIED: u64 = 2;
+const REQUEST: u64 = 6;
+const REQUEST_RESPONSE: u64 = 7;
+
+/// Which events an action happens on: `start`, `split` (every split), or the name of an event,
+/// e.g. `vitellary`, `game_complete` or `reset`.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Trigger {
+    Split,
+    Event(Event),
+}
+
+impl FromStr for Trigger {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "start" => Ok(Trigger::Event(Event::NewGame)),
+            "split" => Ok(Trigger::Split),
+            _ => serde_json::from_value(Value::String(s.into()))
+                .map(Trigger::Event)
+                .map_err(|_| {
+                    format!("unknown event {s:?} (expected start, split, reset, or a split such as vitellary or game_complete)")
+                }),
+        }
+    }
+}
+
+impl Trigger {
+    fn matches(self, event: Event) -> bool {
+        match self {
+            Trigger::Split => event.is_split(),
+            Trigger::Event(trigger) => trigger == event,
+        }
+    }
+}
+
+/// `TRIGGER=SCENE`: switch to a scene when something happens.
+#[derive(Debug, Clone)]
+pub(crate) struct SceneSwitch {
+    trigger: Trigger,
+    scene: String,
+}
+
+impl FromStr for SceneSwitch {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (trigger, scene) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected EVENT=SCENE, got {s:?}"))?;
+        Ok(Self {
+            trigger: trigger.parse()?,
+            scene: scene.into(),
+        })
+    }
+}
+
+/// What to do in OBS, and when.
+pub(crate) struct Actions {
+    pub(crate) scenes: Vec<SceneSwitch>,
+    /// when to save the replay buffer
+    pub(crate) save_replay: Vec<Trigger>,
+    /// a text source to show the last split in
+    pub(crate) split_text: Option<String>,
+    /// a text source to show the current area (or room) in
+    pub(crate) room_text: Option<String>,
+}
+
+/// A connection to obs-websocket, which is made when it's first needed and remade if it fails.
+pub(crate) struct Obs {
+    /// e.g. `ws://127.0.0.1:4455`
+    url: String,
+    password: Option<String>,
+    actions: Actions,
+    link: Reconnect<WebSocket<TcpStream>>,
+    next_request_id: u64,
+    /// the room text we last showed, to only update it when it changes
+    room: Option<String>,
+}
+
+impl Obs {
+    pub(crate) fn new(url: String, password: Option<String>, actions: Actions) -> Self {
+        Self {
+            link: Reconnect::new(format!("OBS at {}", url), RECONNECT_INTERVAL),
+            url,
+            password,
+            actions,
+            next_request_id: 0,
+            room: None,
+        }
+    }
+
+    /// Make sure we're connected, unless we tried too recently. Returns whether we are.
+    fn ensure_connected(&mut self) -> bool {
+        match self
+            .link
+            .ensure(|| connect(&self.url, self.password.as_deref()))
+        {
+            Status::Disconnected => false,
+            Status::Connected => true,
+            Status::Reconnected => {
+                // make sure the room gets shown again
+                self.room = None;
+                true
+            }
+        }
+    }
+
+    /// Make a request, and wait for OBS to answer it.
+    fn request(&mut self, request_type: &str, data: &Value) -> Result<()> {
+        let websocket = self.link.get().ok_or_else(|| anyhow!("not connected"))?;
+        self.next_request_id += 1;
+        let id = self.next_request_id.to_string();
+        let request = json!({ "requestType": request_type, "requestId": id, "requestData": data });
+        send(websocket, REQUEST, &request)?;
+        loop {
+            let response = receive(websocket, REQUEST_RESPONSE)?;
+            if response["requestId"] != id.as_str() {
+                continue;
+            }
+            let status = &response["requestStatus"];
+            if status["result"] != true {
+                self.link.refused(request_type, status["comment"].as_str());
+            }
+            return Ok(());
+        }
+    }
+
+    /// Make a request, if we're connected. If the connection has gone stale (e.g. OBS was
+    /// restarted), reconnect straight away and try again once.
+    fn try_request(&mut self, request_type: &str, data: &Value) {
+        log::debug!("OBS: {} {}", request_type, data);
+        for retry in [false, true] {
+            if !self.ensure_connected() {
+                log::debug!("not connected to OBS; skipping {}", request_type);
+                return;
+            }
+            match self.request(request_type, data) {
+                Ok(()) => return,
+                Err(e) => {
+                    self.link.lost(&e, !retry);
+                    if retry {
+                        return;
+                    }
+                }
+            }
+        }
+    }
+
+    fn set_text(&mut self, source: &str, text: &str) {
+        self.try_request(
+            "SetInputSettings",
+            &json!({ "inputName": source, "inputSettings": { "text": text } }),
+        );
+    }
+}
+
+impl Sink for Obs {
+    fn name(&self) -> String {
+        format!("OBS at {}", self.url)
+    }
+
+    fn update(&mut self, update: &Update) -> Result<()> {
+        if !self.ensure_connected() {
+            return Ok(());
+        }
+        if let Some(event) = update.event {
+            let scenes = self
+                .actions
+                .scenes
+                .iter()
+                .filter(|switch| switch.trigger.matches(event))
+                .map(|switch| switch.scene.clone())
+                .collect::<Vec<_>>();
+            for scene in scenes {
+                self.try_request("SetCurrentProgramScene", &json!({ "sceneName": scene }));
+            }
+            if let Some(source) = self.actions.split_text.clone() {
+                match event {
+                    Event::NewGame | Event::Reset => self.set_text(&source, ""),
+                    event if event.is_split() => self.set_text(&source, &event.to_string()),
+                    _ => {}
+                }
+            }
+            if self
+                .actions
+                .save_replay
+                .iter()
+                .any(|trigger| trigger.matches(event))
+            {
+                self.try_request("SaveReplayBuffer", &json!({}));
+            }
+        }
+        if let Some(source) = self.actions.room_text.clone() {
+            let room = describe_room(update.state.room);
+            if self.room.as_ref() != Some(&room) {
+                self.set_text(&source, &room);
+                self.room = Some(room);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Connect to obs-websocket at `url` and identify ourselves, answering the authentication challenge
+/// if there is one.
+fn connect(url: &str, password: Option<&str>) -> Result<WebSocket<TcpStream>> {
+    let uri = url.parse::<tungstenite::http::Uri>()?;
+    let host = uri.host().ok_or_else(|| anyhow!("no host in {}", url))?;
+    let address = (host, uri.port_u16().unwrap_or(4455))
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| anyhow!("{} didn't resolve to anything", host))?;
+    let stream = TcpStream::connect_timeout(&address, TIMEOUT)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    let (mut websocket, _) =
+        tungstenite::client(url, stream).map_err(|e| anyhow!("handshake failed: {}", e))?;
+
+    let hello = receive(&mut websocket, HELLO)?;
+    let mut identify = json!({ "rpcVersion": RPC_VERSION, "eventSubscriptions": 0 });
+    if let Some(auth) = hello.get("authentication") {
+        let password =
+            password.ok_or_else(|| anyhow!("OBS wants a password; use --obs-password"))?;
+        let challenge = auth["challenge"].as_str().unwrap_or_default();
+        let salt = auth["salt"].as_str().unwrap_or_default();
+        identify["authentication"] = authentication(password, salt, challenge).into();
+    }
+    send(&mut websocket, IDENTIFY, &identify)?;
+    receive(&mut websocket, IDENTIFIED)?;
+    Ok(websocket)
+}
+
+/// obs-websocket's answer to a challenge: `base64(sha256(base64(sha256(password + salt)) + challenge))`.
+fn authentication(password: &str, salt: &str, challenge: &str) -> String {
+    let secret = BASE64.encode(Sha256::digest(format!("{password}{salt}")));
+    BASE64.encode(Sha256::digest(format!("{secret}{challenge}")))
+}
+
+fn send(websocket: &mut WebSocket<TcpStream>, op: u64, data: &Value) -> Result<()> {
+    let message = json!({ "op": op, "d": data }).to_string();
+    Ok(websocket.write_message(Message::Text(message))?)
+}
+
+/// Wait for a message with opcode `op`, skipping any others, and return its data.
+fn receive(websocket: &mut WebSocket<TcpStream>, op: u64) -> Result<Value> {
+    loop {
+        let text = match websocket.read_message()? {
+            Message::Text(text) => text,
+            Message::Close(Some(frame)) => {
+                bail!(
+                    "OBS closed the connection: {} ({})",
+                    frame.reason,
+                    frame.code
+                );
+            }
+            Message::Close(None) => bail!("OBS closed the connection"),
+            _ => continue,
+        };
+        let mut message: Value =
+            serde_json::from_str(&text).context("OBS sent something that isn't JSON")?;
+        if message["op"] == op {
+            return Ok(message["d"].take());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+    use std::thread;
+
+    const PASSWORD: &str = "supersecretpassword";
+    const SALT: &str = "lM1GncleQOaCu9lT1yeUZhFYnqhsLLP1G5lAGo3ixaI=";
+    const CHALLENGE: &str = "+IxH4CnCiqpX1rM9scsNynZzbOe4KhDeYcTNS3PDaeY=";
+
+    #[test]
+    fn authentication_matches_the_protocol_docs() {
+        assert_eq!(
+            authentication(PASSWORD, SALT, CHALLENGE),
+            "1Ct943GAT+6YQUUX47Ia/ncufilbe6+oD6lY+5kaCu4="
+        );
+    }
+
+    /// A stand-in for obs-websocket, which wants a password and sends back every request it gets.
+    fn fake_obs(listener: &TcpListener, requests: &mpsc::Sender<(String, Value)>) {
+        let (stream, _) = listener.accept().unwrap();
+        let mut websocket = tungstenite::accept(stream).unwrap();
+        let auth = json!({ "challenge": CHALLENGE, "salt": SALT });
+        let hello = json!({ "rpcVersion": 1, "authentication": auth });
+        send(&mut websocket, HELLO, &hello).unwrap();
+        let identify = receive(&mut websocket, IDENTIFY).unwrap();
+        assert_eq!(
+            identify["authentication"],
+            authentication(PASSWORD, SALT, CHALLENGE)
+        );
+        send(
+            &mut websocket,
+            IDENTIFIED,
+            &json!({ "negotiatedRpcVersion": 1 }),
+        )
+        .unwrap();
+        while let Ok(mut request) = receive(&mut websocket, REQUEST) {
+            let response = json!({
+                "requestType": request["requestType"],
+                "requestId": request["requestId"],
+                "requestStatus": { "result": true, "code": 100 },
+            });
+            send(&mut websocket, REQUEST_RESPONSE, &response).unwrap();
+            let request_type = request["requestType"].as_str().unwrap().to_string();
+            requests
+                .send((request_type, request["requestData"].take()))
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn controls_obs() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        let (sender, requests) = mpsc::channel();
+        thread::spawn(move || fake_obs(&listener, &sender));
+
+        let actions = Actions {
+            scenes: vec!["split=Splits".parse().unwrap()],
+            save_replay: vec!["game_complete".parse().unwrap()],
+            split_text: Some("Split".into()),
+            room_text: Some("Room".into()),
+        };
+        let mut obs = Obs::new(url, Some(PASSWORD.into()), actions);
+        let expect = |events: &[(&str, Value)]| {
+            for (request_type, data) in events {
+                let request = requests.recv_timeout(TIMEOUT).unwrap();
+                assert_eq!((request.0.as_str(), &request.1), (*request_type, data));
+            }
+        };
+        let text = |source: &str, text: &str| {
+            (
+                "SetInputSettings",
+                json!({ "inputName": source, "inputSettings": { "text": text } }),
+            )
+        };
+
+        let start = Update::test(Duration::ZERO, (113, 100), 0, Some(Event::NewGame));
+        obs.update(&start).unwrap();
+        expect(&[text("Split", ""), text("Room", "Warp Zone")]);
+
+        // the room text only changes when the area does
+        let split = Update::test(
+            Duration::from_secs(83),
+            (115, 100),
+            0,
+            Some(Event::Verdigris),
+        );
+        obs.update(&split).unwrap();
+        expect(&[
+            ("SetCurrentProgramScene", json!({ "sceneName": "Splits" })),
+            text("Split", "Verdigris"),
+        ]);
+
+        let end = Update::test(
+            Duration::from_secs(90),
+            (46, 54),
+            0,
+            Some(Event::GameComplete),
+        );
+        obs.update(&end).unwrap();
+        expect(&[
+            ("SetCurrentProgramScene", json!({ "sceneName": "Splits" })),
+            text("Split", "Game complete"),
+            ("SaveReplayBuffer", json!({})),
+            text("Room", "Outside Dimension VVVVVV"),
+        ]);
+    }
+}
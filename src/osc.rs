@@ -0,0 +1,289 @@
+//! Sending updates as OSC (Open Sound Control) messages over UDP, for lighting, audio and video
+//! tools. The messages are:
+//!
+//! - `/vvvvvv/event ,sf`: the name of each event (as in the JSON stream, e.g. `new_game`) and the
+//!   game time in seconds
+//! - `/vvvvvv/split ,sf`: the name of each split (e.g. `Violet`) and the game time in seconds
+//! - `/vvvvvv/igt ,f`: the game time in seconds, whenever it changes
+//! - `/vvvvvv/room ,ii`: the room, whenever it changes
+//! - `/vvvvvv/deaths ,i`: the death count, whenever it changes, if we know where the game keeps it
+
+use crate::game::Update;
+use crate::sink::Sink;
+use anyhow::{anyhow, Context, Result};
+use std::net::{ToSocketAddrs, UdpSocket};
+
+/// An argument to an OSC message.
+enum Argument {
+    Int(i32),
+    Float(f32),
+    String(String),
+}
+
+/// Append `s` as an OSC string: null-terminated, and padded to a multiple of 4 bytes.
+fn push_string(buf: &mut Vec<u8>, s: &str) {
+    buf.extend_from_slice(s.as_bytes());
+    buf.push(0);
+    while !buf.len().is_multiple_of(4) {
+        buf.push(0);
+    }
+}
+
+/// Encode an OSC 1.0 message.
+fn encode(address: &str, arguments: &[Argument]) -> Vec<u8> {
+    let mut buf = vec![];
+    push_string(&mut buf, address);
+    let tags = arguments
+        .iter()
+        .map(|argument| match argument {
+            Argument::Int(_) => 'i',
+            Argument::Float(_) => 'f',
+            Argument::String(_) => 's',
+        })
+        .collect::<String>();
+    push_string(&mut buf, &format!(",{}", tags));
+    for argument in arguments {
+        match argument {
+            Argument::Int(i) => buf.extend_from_slice(&i.to_be_bytes()),
+            Argument::Float(f) => buf.extend_from_slice(&f.to_be_bytes()),
+            Argument::String(s) => push_string(&mut buf, s),
+        }
+    }
+    buf
+}
+
+/// A UDP address to send OSC messages to.
+pub(crate) struct Osc {
+    target: String,
+    socket: UdpSocket,
+    /// the last update we sent, to only send what's changed
+    sent: Option<Update>,
+}
+
+impl Osc {
+    /// Start sending to `target` (`HOST:PORT`).
+    pub(crate) fn open(target: String) -> Result<Self> {
+        let open = || -> Result<UdpSocket> {
+            let address = target
+                .to_socket_addrs()?
+                .next()
+                .ok_or_else(|| anyhow!("it didn't resolve to anything"))?;
+            let any = if address.is_ipv4() {
+                "0.0.0.0:0"
+            } else {
+                "[::]:0"
+            };
+            let socket = UdpSocket::bind(any)?;
+            socket.connect(address)?;
+            Ok(socket)
+        };
+        let socket = open().with_context(|| format!("failed to open OSC target {}", target))?;
+        Ok(Self {
+            target,
+            socket,
+            sent: None,
+        })
+    }
+
+    fn send(&self, address: &str, arguments: &[Argument]) {
+        // UDP has nobody to tell us whether it arrived, and an error now is probably about an
+        // earlier message that nothing was listening for, so errors aren't worth stopping for
+        if let Err(e) = self.socket.send(&encode(address, arguments)) {
+            log::debug!("failed to send {} to {}: {}", address, self.target, e);
+        }
+    }
+}
+
+impl Sink for Osc {
+    fn name(&self) -> String {
+        format!("OSC to {}", self.target)
+    }
+
+    #[allow(clippy::cast_possible_truncation)] // f32 is plenty for game times
+    fn update(&mut self, update: &Update) -> Result<()> {
+        let time = update.time.as_secs_f64() as f32;
+        if let Some(event) = update.event {
+            let name = serde_json::to_value(event)?;
+            self.send(
+                "/vvvvvv/event",
+                &[
+                    Argument::String(name.as_str().unwrap_or_default().into()),
+                    Argument::Float(time),
+                ],
+            );
+            if event.is_split() {
+                self.send(
+                    "/vvvvvv/split",
+                    &[Argument::String(event.to_string()), Argument::Float(time)],
+                );
+            }
+        }
+        if self.sent.is_none_or(|sent| sent.time != update.time) {
+            self.send("/vvvvvv/igt", &[Argument::Float(time)]);
+        }
+        let (x, y) = update.state.room;
+        if self.sent.is_none_or(|sent| sent.state.room != (x, y)) {
+            self.send(
+                "/vvvvvv/room",
+                &[
+                    Argument::Int(x.try_into().unwrap_or(i32::MAX)),
+                    Argument::Int(y.try_into().unwrap_or(i32::MAX)),
+                ],
+            );
+        }
+        if let Some(deaths) = update.state.deaths {
+            if self
+                .sent
+                .is_none_or(|sent| sent.state.deaths != Some(deaths))
+            {
+                self.send(
+                    "/vvvvvv/deaths",
+                    &[Argument::Int(deaths.try_into().unwrap_or(i32::MAX))],
+                );
+            }
+        }
+        self.sent = Some(*update);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::Event;
+    use std::time::Duration;
+
+    #[derive(Debug, PartialEq)]
+    enum Decoded {
+        Int(i32),
+        Float(f32),
+        String(String),
+    }
+
+    /// Read an OSC string at `*at`, checking its padding, and move past it.
+    fn read_string(packet: &[u8], at: &mut usize) -> String {
+        let len = packet[*at..].iter().position(|&b| b == 0).unwrap();
+        let s = String::from_utf8(packet[*at..*at + len].to_vec()).unwrap();
+        let end = (*at + len + 1).next_multiple_of(4);
+        assert!(packet[*at + len..end].iter().all(|&b| b == 0));
+        *at = end;
+        s
+    }
+
+    fn decode(packet: &[u8]) -> (String, String, Vec<Decoded>) {
+        assert!(packet.len().is_multiple_of(4));
+        let mut at = 0;
+        let address = read_string(packet, &mut at);
+        let tags = read_string(packet, &mut at);
+        let word = |at: &mut usize| {
+            let bytes: [u8; 4] = packet[*at..*at + 4].try_into().unwrap();
+            *at += 4;
+            bytes
+        };
+        let mut arguments = vec![];
+        for tag in tags.chars().skip(1) {
+            arguments.push(match tag {
+                'i' => Decoded::Int(i32::from_be_bytes(word(&mut at))),
+                'f' => Decoded::Float(f32::from_be_bytes(word(&mut at))),
+                's' => Decoded::String(read_string(packet, &mut at)),
+                _ => panic!("unexpected tag {tag}"),
+            });
+        }
+        assert_eq!(at, packet.len());
+        (address, tags, arguments)
+    }
+
+    #[test]
+    fn sends_what_changed() {
+        let listener = UdpSocket::bind("127.0.0.1:0").unwrap();
+        listener
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut osc = Osc::open(listener.local_addr().unwrap().to_string()).unwrap();
+        let received = || {
+            let mut buf = [0; 1500];
+            let len = listener.recv(&mut buf).unwrap();
+            decode(&buf[..len])
+        };
+        let message = |address: &str, tags: &str, arguments| {
+            (address.to_string(), tags.to_string(), arguments)
+        };
+
+        osc.update(&Update::test(
+            Duration::ZERO,
+            (115, 100),
+            0,
+            Some(Event::NewGame),
+        ))
+        .unwrap();
+        assert_eq!(
+            received(),
+            message(
+                "/vvvvvv/event",
+                ",sf",
+                vec![Decoded::String("new_game".into()), Decoded::Float(0.0)]
+            )
+        );
+        assert_eq!(
+            received(),
+            message("/vvvvvv/igt", ",f", vec![Decoded::Float(0.0)])
+        );
+        assert_eq!(
+            received(),
+            message(
+                "/vvvvvv/room",
+                ",ii",
+                vec![Decoded::Int(115), Decoded::Int(100)]
+            )
+        );
+        assert_eq!(
+            received(),
+            message("/vvvvvv/deaths", ",i", vec![Decoded::Int(0)])
+        );
+
+        // nothing has changed, so there's nothing to send
+        osc.update(&Update::test(Duration::ZERO, (115, 100), 0, None))
+            .unwrap();
+        let time = Duration::from_millis(1500);
+        let violet = Update::test(time, (115, 100), 1, Some(Event::Violet));
+        osc.update(&violet).unwrap();
+        assert_eq!(
+            received(),
+            message(
+                "/vvvvvv/event",
+                ",sf",
+                vec![Decoded::String("violet".into()), Decoded::Float(1.5)]
+            )
+        );
+        assert_eq!(
+            received(),
+            message(
+                "/vvvvvv/split",
+                ",sf",
+                vec![Decoded::String("Violet".into()), Decoded::Float(1.5)]
+            )
+        );
+        assert_eq!(
+            received(),
+            message("/vvvvvv/igt", ",f", vec![Decoded::Float(1.5)])
+        );
+        // the room is the same, so only the deaths follow
+        assert_eq!(
+            received(),
+            message("/vvvvvv/deaths", ",i", vec![Decoded::Int(1)])
+        );
+
+        osc.update(&Update::test(time, (116, 100), 1, None))
+            .unwrap();
+        assert_eq!(
+            received(),
+            message(
+                "/vvvvvv/room",
+                ",ii",
+                vec![Decoded::Int(116), Decoded::Int(100)]
+            )
+        );
+        listener.set_nonblocking(true).unwrap();
+        assert!(listener.recv(&mut [0; 1500]).is_err());
+    }
+}
@@ -0,0 +1,82 @@
+//! Writing updates somewhere other than a socket, for scripts and logs.
+
+use crate::game::Update;
+use crate::sink::Sink;
+use crate::stream::Encoder;
+use anyhow::{Context, Result};
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// `ndjson` for stdout, or `ndjson:PATH` to append to a file
+#[derive(Debug, Clone)]
+pub(crate) enum Output {
+    /// one `/stream` message per line, for each event and each change of state
+    Ndjson(Option<PathBuf>),
+}
+
+impl FromStr for Output {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (format, path) = match s.split_once(':') {
+            Some((format, path)) => (format, Some(PathBuf::from(path))),
+            None => (s, None),
+        };
+        match format {
+            "ndjson" => Ok(Output::Ndjson(path)),
+            _ => Err(format!(
+                "unknown output format {format:?} (expected ndjson)"
+            )),
+        }
+    }
+}
+
+impl Output {
+    /// Open the output, ready to write updates to.
+    pub(crate) fn open(&self) -> Result<Writer> {
+        let Output::Ndjson(path) = self;
+        let out: Box<dyn Write + Send> = match path {
+            Some(path) => Box::new(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .with_context(|| format!("failed to open {}", path.display()))?,
+            ),
+            None => Box::new(io::stdout()),
+        };
+        let name = path
+            .as_ref()
+            .map_or_else(|| "stdout".into(), |path| path.display().to_string());
+        Ok(Writer {
+            out,
+            name,
+            encoder: Encoder::new(false),
+        })
+    }
+}
+
+/// An output that's been opened.
+pub(crate) struct Writer {
+    out: Box<dyn Write + Send>,
+    /// where it's writing to
+    name: String,
+    encoder: Encoder,
+}
+
+impl Sink for Writer {
+    fn name(&self) -> String {
+        format!("output to {}", self.name)
+    }
+
+    fn update(&mut self, update: &Update) -> Result<()> {
+        if let Some(message) = self.encoder.encode(update) {
+            writeln!(self.out, "{}", message)?;
+            // flushing every line, so that whatever's reading gets it straight away
+            self.out.flush()?;
+        }
+        Ok(())
+    }
+}
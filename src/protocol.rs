@@ -0,0 +1,502 @@
+//! The commands we send to timers.
+//!
+//! Current versions of LiveSplit One take JSON commands like `{"command":"split"}`; older ones
+//! take plain text like `split`, and so does LiveSplit's server component, with slightly different
+//! names for some commands (e.g. `skipsplit` rather than `skip`).
+//!
+//! The timer's game time is kept paused, and only moves when we set it. Otherwise livesplit-core
+//! carries on counting in real time from the last time we sent, e.g. in menus where the game's own
+//! timer has stopped.
+
+#![allow(clippy::doc_markdown)]
+
+use crate::game::{Event, Update};
+use serde::Serialize;
+use serde_json::Value;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+/// how long to wait for a new client to tell us what its timer is doing
+const SYNC_TIMEOUT: Duration = Duration::from_millis(500);
+/// how often to ask clients what their timer is doing, in case the runner changed it
+const QUERY_INTERVAL: Duration = Duration::from_secs(2);
+/// don't ask more often than this, even if the timer keeps changing
+const MIN_QUERY_INTERVAL: Duration = Duration::from_millis(100);
+/// LiveSplit One's events that change the timer's phase or split index. The others (e.g.
+/// `GameTimeSet`, which follows every time we set the game time) don't matter to us.
+const PHASE_EVENTS: [&str; 10] = [
+    "Started",
+    "Splitted",
+    "Finished",
+    "Reset",
+    "SplitUndone",
+    "SplitSkipped",
+    "Paused",
+    "Resumed",
+    "PausesUndone",
+    "PausesUndoneAndResumed",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Protocol {
+    Json,
+    Legacy,
+    /// LiveSplit's server component
+    LiveSplit,
+}
+
+impl Protocol {
+    /// The protocol a client asked for with the path it connected to, e.g. `ws://127.0.0.1:5555/legacy`.
+    pub(crate) fn from_path(path: &str) -> Option<Self> {
+        path.trim_matches('/').parse().ok()
+    }
+}
+
+impl FromStr for Protocol {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(Protocol::Json),
+            "legacy" => Ok(Protocol::Legacy),
+            "livesplit" => Ok(Protocol::LiveSplit),
+            _ => Err(format!(
+                "unknown protocol {s:?} (expected json, legacy or livesplit)"
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "command", rename_all = "camelCase")]
+pub(crate) enum Command {
+    Start,
+    Split,
+    SkipSplit,
+    // we never take back a split ourselves
+    #[allow(dead_code)]
+    UndoSplit,
+    Reset,
+    InitializeGameTime,
+    SetGameTime {
+        time: String,
+    },
+    PauseGameTime,
+}
+
+impl Command {
+    fn set_game_time(time: Duration) -> Self {
+        Command::SetGameTime {
+            time: format!("{}.{:02}", time.as_secs(), time.subsec_nanos() / 10_000_000),
+        }
+    }
+
+    fn encode(&self, protocol: Protocol) -> String {
+        match protocol {
+            Protocol::Json => serde_json::to_string(self).expect("commands always serialize"),
+            Protocol::Legacy => match self {
+                Command::Start => "start".into(),
+                Command::Split => "split".into(),
+                Command::SkipSplit => "skip".into(),
+                Command::UndoSplit => "undo".into(),
+                Command::Reset => "reset".into(),
+                Command::InitializeGameTime => "initgametime".into(),
+                Command::SetGameTime { time } => format!("setgametime {time}"),
+                Command::PauseGameTime => "pausegametime".into(),
+            },
+            Protocol::LiveSplit => match self {
+                Command::Start => "starttimer".into(),
+                Command::SkipSplit => "skipsplit".into(),
+                Command::UndoSplit => "unsplit".into(),
+                _ => self.encode(Protocol::Legacy),
+            },
+        }
+    }
+}
+
+/// What LiveSplit One's timer is doing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Phase {
+    NotRunning,
+    Running,
+    Paused,
+    Ended,
+}
+
+impl FromStr for Phase {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "NotRunning" => Ok(Phase::NotRunning),
+            "Running" => Ok(Phase::Running),
+            "Paused" => Ok(Phase::Paused),
+            "Ended" => Ok(Phase::Ended),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Get the phase and split index out of the response to `getCurrentState`.
+///
+/// LiveSplit One has sent this as `{"state":"Running","index":2}`, `{"Running":2}` and
+/// `"NotRunning"`, so we take any of them.
+fn parse_state(state: &Value) -> Option<(Phase, Option<usize>)> {
+    let index = |index: &Value| index.as_u64().and_then(|index| usize::try_from(index).ok());
+    match state {
+        Value::String(phase) => Some((phase.parse().ok()?, None)),
+        Value::Object(fields) => {
+            if let Some(Value::String(phase)) = fields.get("state") {
+                return Some((phase.parse().ok()?, fields.get("index").and_then(index)));
+            }
+            let (phase, value) = fields.iter().next()?;
+            Some((phase.parse().ok()?, index(value)))
+        }
+        _ => None,
+    }
+}
+
+/// Our view of one LiveSplit One client: what we've sent it, and what its timer is doing.
+pub(crate) struct Session {
+    protocol: Protocol,
+    /// the game time we last sent
+    time: Option<Duration>,
+    /// the timer's phase, if the client has told us
+    phase: Option<Phase>,
+    /// the index of the timer's current split
+    index: usize,
+    /// how many splits there have been in the current run
+    splits: usize,
+    connected: Instant,
+    /// whether the client has answered a query yet
+    answered: bool,
+    /// whether the timer might have changed since we last asked about it
+    stale: bool,
+    last_query: Option<Instant>,
+}
+
+impl Session {
+    /// `splits` is how many splits the run in progress has had, if the client missed them.
+    pub(crate) fn new(protocol: Protocol, splits: usize) -> Self {
+        Self {
+            protocol,
+            time: None,
+            phase: None,
+            index: 0,
+            splits,
+            connected: Instant::now(),
+            answered: false,
+            stale: true,
+            last_query: None,
+        }
+    }
+
+    /// Whether we're still waiting to hear what the timer is doing, before sending any updates.
+    ///
+    /// Clients that never answer (e.g. older versions of LiveSplit One) just get every update.
+    pub(crate) fn is_syncing(&self) -> bool {
+        !self.answered && self.connected.elapsed() < SYNC_TIMEOUT
+    }
+
+    /// The messages asking the client what its timer is doing, if it's time to ask again.
+    pub(crate) fn queries(&mut self) -> Vec<String> {
+        if !self.answered && !self.is_syncing() {
+            // it isn't going to answer
+            return vec![];
+        }
+        let due = self
+            .last_query
+            .is_none_or(|last| last.elapsed() >= QUERY_INTERVAL);
+        if !(self.stale || due)
+            || self
+                .last_query
+                .is_some_and(|last| last.elapsed() < MIN_QUERY_INTERVAL)
+        {
+            return vec![];
+        }
+        self.stale = false;
+        self.last_query = Some(Instant::now());
+        match self.protocol {
+            Protocol::Json => vec![r#"{"command":"getCurrentState"}"#.into()],
+            Protocol::Legacy | Protocol::LiveSplit => {
+                vec!["getcurrenttimerphase".into(), "getsplitindex".into()]
+            }
+        }
+    }
+
+    /// Handle a message from the client.
+    pub(crate) fn receive(&mut self, message: &str) {
+        match self.protocol {
+            Protocol::Json => {
+                let Ok(message) = serde_json::from_str::<Value>(message) else {
+                    log::debug!("ignoring message {:?}", message);
+                    return;
+                };
+                if let Some(event) = message.get("event") {
+                    // the runner did something, or it's the result of one of our commands
+                    if event
+                        .as_str()
+                        .is_some_and(|event| PHASE_EVENTS.contains(&event))
+                    {
+                        self.stale = true;
+                    }
+                } else if let Some(error) = message.get("error") {
+                    log::debug!("command failed: {}", error);
+                } else if let Some((phase, index)) = message.get("success").and_then(parse_state) {
+                    self.set_state(phase, index);
+                }
+            }
+            Protocol::Legacy | Protocol::LiveSplit => {
+                let message = message.trim();
+                if let Ok(phase) = message.parse() {
+                    self.set_state(phase, None);
+                } else if let Ok(index) = message.parse::<i64>() {
+                    self.answered = true;
+                    self.index = usize::try_from(index).unwrap_or(0);
+                }
+            }
+        }
+    }
+
+    fn set_state(&mut self, phase: Phase, index: Option<usize>) {
+        if self.phase != Some(phase) {
+            log::debug!("timer is {:?}", phase);
+        }
+        self.answered = true;
+        self.phase = Some(phase);
+        if let Some(index) = index {
+            self.index = index;
+        } else if phase == Phase::NotRunning {
+            self.index = 0;
+        }
+    }
+
+    /// Whether the timer is running (or paused), as far as we know.
+    fn is_running(&self) -> Option<bool> {
+        Some(matches!(self.phase?, Phase::Running | Phase::Paused))
+    }
+
+    /// The messages to send for `update`. The game time is only sent when it changes, and events
+    /// the timer already has (e.g. a run's splits, when it reconnects) are left out.
+    pub(crate) fn encode(&mut self, update: &Update) -> Vec<String> {
+        let mut commands = vec![];
+        if let Some(Event::NewGame) = update.event {
+            self.splits = 0;
+            if self.is_running() != Some(true) {
+                // starting the timer uninitializes its game time
+                commands.extend([
+                    Command::Start,
+                    Command::InitializeGameTime,
+                    Command::PauseGameTime,
+                ]);
+                self.phase = self.phase.map(|_| Phase::Running);
+                self.index = 0;
+                self.time = None;
+            }
+        }
+        if self.time != Some(update.time) {
+            commands.push(Command::set_game_time(update.time));
+            self.time = Some(update.time);
+        }
+        match update.event {
+            None | Some(Event::NewGame | Event::Detached | Event::Attached) => {}
+            Some(Event::Reset) => {
+                if self.phase != Some(Phase::NotRunning) {
+                    commands.push(Command::Reset);
+                    self.phase = self.phase.map(|_| Phase::NotRunning);
+                    self.index = 0;
+                }
+            }
+            // everything else is a split
+            Some(_) => {
+                self.splits += 1;
+                match self.is_running() {
+                    None => commands.push(Command::Split),
+                    Some(true) if self.index < self.splits => {
+                        // splits that happened while we weren't connected have lost their times
+                        for _ in self.index + 1..self.splits {
+                            commands.push(Command::SkipSplit);
+                        }
+                        commands.push(Command::Split);
+                        self.index = self.splits;
+                    }
+                    Some(_) => log::debug!(
+                        "not splitting; timer is {:?} on split {}",
+                        self.phase,
+                        self.index
+                    ),
+                }
+            }
+        }
+        if update.event.is_some() {
+            self.stale = true;
+        }
+        commands
+            .iter()
+            .map(|command| command.encode(self.protocol))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn update(millis: u64, event: Option<Event>) -> Update {
+        Update::test(Duration::from_millis(millis), (115, 100), 0, event)
+    }
+
+    /// A session whose client has told us what its timer is doing.
+    fn answered(protocol: Protocol, splits: usize, answers: &[&str]) -> Session {
+        let mut session = Session::new(protocol, splits);
+        assert!(session.is_syncing());
+        assert!(!session.queries().is_empty());
+        for answer in answers {
+            session.receive(answer);
+        }
+        assert!(!session.is_syncing());
+        session
+    }
+
+    #[test]
+    fn runs_a_timer() {
+        let mut session = answered(Protocol::Json, 0, &[r#"{"success":"NotRunning"}"#]);
+        assert_eq!(
+            session.encode(&update(0, Some(Event::NewGame))),
+            [
+                r#"{"command":"start"}"#,
+                r#"{"command":"initializeGameTime"}"#,
+                r#"{"command":"pauseGameTime"}"#,
+                r#"{"command":"setGameTime","time":"0.00"}"#,
+            ]
+        );
+        // the game time is only sent when it changes
+        assert!(session.encode(&update(0, None)).is_empty());
+        assert_eq!(
+            session.encode(&update(12_500, Some(Event::Verdigris))),
+            [
+                r#"{"command":"setGameTime","time":"12.50"}"#,
+                r#"{"command":"split"}"#,
+            ]
+        );
+        assert_eq!(
+            session.encode(&update(12_500, Some(Event::Reset))),
+            [r#"{"command":"reset"}"#]
+        );
+        // it's already reset
+        assert!(session
+            .encode(&update(12_500, Some(Event::Reset)))
+            .is_empty());
+    }
+
+    #[test]
+    fn leaves_a_running_timer_running() {
+        let mut session = answered(
+            Protocol::Json,
+            0,
+            &[r#"{"success":{"state":"Running","index":0}}"#],
+        );
+        assert_eq!(
+            session.encode(&update(0, Some(Event::NewGame))),
+            [r#"{"command":"setGameTime","time":"0.00"}"#]
+        );
+    }
+
+    #[test]
+    fn skips_splits_missed_while_disconnected() {
+        // two splits happened while the timer wasn't connected, and it hasn't seen either
+        let mut session = answered(Protocol::Json, 2, &[r#"{"success":{"Running":0}}"#]);
+        assert_eq!(
+            session.encode(&update(60_000, Some(Event::Violet))),
+            [
+                r#"{"command":"setGameTime","time":"60.00"}"#,
+                r#"{"command":"skipSplit"}"#,
+                r#"{"command":"skipSplit"}"#,
+                r#"{"command":"split"}"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn doesnt_split_what_the_timer_already_has() {
+        // the runner split by hand while we weren't connected
+        let mut session = answered(Protocol::Json, 1, &[r#"{"success":{"Running":2}}"#]);
+        assert_eq!(
+            session.encode(&update(60_000, Some(Event::Violet))),
+            [r#"{"command":"setGameTime","time":"60.00"}"#]
+        );
+        // nor past the end of the run
+        let mut session = answered(Protocol::Json, 7, &[r#"{"success":{"Ended":8}}"#]);
+        assert_eq!(
+            session.encode(&update(3_600_000, Some(Event::GameComplete))),
+            [r#"{"command":"setGameTime","time":"3600.00"}"#]
+        );
+    }
+
+    #[test]
+    fn resets_only_a_running_timer() {
+        let mut session = answered(Protocol::Json, 0, &[r#"{"success":"NotRunning"}"#]);
+        assert_eq!(
+            session.encode(&update(5_000, Some(Event::Reset))),
+            [r#"{"command":"setGameTime","time":"5.00"}"#]
+        );
+        // a timer that never told us what it's doing might be running
+        let mut session = Session::new(Protocol::Json, 0);
+        assert_eq!(
+            session.encode(&update(5_000, Some(Event::Reset))),
+            [
+                r#"{"command":"setGameTime","time":"5.00"}"#,
+                r#"{"command":"reset"}"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn asks_again_after_the_runner_changes_the_timer() {
+        let mut session = answered(Protocol::Json, 0, &[r#"{"success":"NotRunning"}"#]);
+        std::thread::sleep(MIN_QUERY_INTERVAL);
+        // these follow our own commands, and don't change the phase or split
+        session.receive(r#"{"event":"GameTimeSet"}"#);
+        session.receive(r#"{"success":null}"#);
+        assert!(session.queries().is_empty());
+        session.receive(r#"{"event":"Splitted"}"#);
+        assert_eq!(session.queries(), [r#"{"command":"getCurrentState"}"#]);
+    }
+
+    #[test]
+    fn speaks_the_text_protocols() {
+        let mut session = answered(Protocol::Legacy, 1, &["NotRunning", "0"]);
+        assert_eq!(
+            session.encode(&update(0, Some(Event::NewGame))),
+            ["start", "initgametime", "pausegametime", "setgametime 0.00"]
+        );
+        assert_eq!(
+            session.encode(&update(1_000, Some(Event::Verdigris))),
+            ["setgametime 1.00", "split"]
+        );
+        assert_eq!(
+            session.encode(&update(1_500, Some(Event::Reset))),
+            ["setgametime 1.50", "reset"]
+        );
+
+        let mut session = answered(Protocol::LiveSplit, 1, &["Running", "0"]);
+        assert_eq!(
+            session.encode(&update(2_000, Some(Event::Vermilion))),
+            ["setgametime 2.00", "skipsplit", "split"]
+        );
+        assert_eq!(
+            session.encode(&update(2_500, Some(Event::Reset))),
+            ["setgametime 2.50", "reset"]
+        );
+        let mut session = answered(Protocol::LiveSplit, 0, &["NotRunning", "0"]);
+        assert_eq!(
+            session.encode(&update(0, Some(Event::NewGame))),
+            [
+                "starttimer",
+                "initgametime",
+                "pausegametime",
+                "setgametime 0.00"
+            ]
+        );
+    }
+}
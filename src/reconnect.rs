@@ -0,0 +1,94 @@
+//! Keeping a connection to something that may not be running yet, or may go away (OBS, Discord):
+//! connecting when it's first needed, not trying again too often, and only warning about the
+//! first of a series of failures.
+
+use anyhow::{Error, Result};
+use std::time::{Duration, Instant};
+
+/// What [`Reconnect::ensure`] found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Status {
+    Disconnected,
+    Connected,
+    /// connected just now, so anything shown through an older connection needs showing again
+    Reconnected,
+}
+
+pub(crate) struct Reconnect<C> {
+    /// what we're connecting to, for the logs, e.g. `OBS at ws://127.0.0.1:4455`
+    what: String,
+    /// the least time between attempts
+    interval: Duration,
+    connection: Option<C>,
+    last_attempt: Option<Instant>,
+    /// whether we've already warned about failing to connect
+    warned: bool,
+}
+
+impl<C> Reconnect<C> {
+    pub(crate) fn new(what: String, interval: Duration) -> Self {
+        Self {
+            what,
+            interval,
+            connection: None,
+            last_attempt: None,
+            warned: false,
+        }
+    }
+
+    /// Connect with `connect` if we aren't connected, unless we tried too recently.
+    pub(crate) fn ensure(&mut self, connect: impl FnOnce() -> Result<C>) -> Status {
+        if self.connection.is_some() {
+            return Status::Connected;
+        }
+        if self
+            .last_attempt
+            .is_some_and(|last| last.elapsed() < self.interval)
+        {
+            return Status::Disconnected;
+        }
+        self.last_attempt = Some(Instant::now());
+        match connect() {
+            Ok(connection) => {
+                log::info!("connected to {}", self.what);
+                self.connection = Some(connection);
+                self.warned = false;
+                Status::Reconnected
+            }
+            Err(e) if !self.warned => {
+                log::warn!("failed to connect to {}: {:#}; retrying", self.what, e);
+                self.warned = true;
+                Status::Disconnected
+            }
+            Err(e) => {
+                log::debug!("failed to connect to {}: {:#}", self.what, e);
+                Status::Disconnected
+            }
+        }
+    }
+
+    /// The connection, if we have one.
+    pub(crate) fn get(&mut self) -> Option<&mut C> {
+        self.connection.as_mut()
+    }
+
+    /// Drop a connection that failed. With `retry_now`, the next [`Reconnect::ensure`] tries again
+    /// straight away, e.g. when the connection had just gone stale.
+    pub(crate) fn lost(&mut self, e: &Error, retry_now: bool) {
+        log::warn!("lost connection to {}: {:#}", self.what, e);
+        self.connection = None;
+        if retry_now {
+            self.last_attempt = None;
+        }
+    }
+
+    /// Log that the other end turned down a request. It's still there, so we stay connected.
+    pub(crate) fn refused(&self, request: &str, reason: Option<&str>) {
+        log::warn!(
+            "{} couldn't do {}: {}",
+            self.what,
+            request,
+            reason.unwrap_or("no reason given")
+        );
+    }
+}
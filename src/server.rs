@@ -0,0 +1,258 @@
+//! The WebSocket and HTTP server, on a TCP address and optionally on a Unix socket.
+
+#![allow(clippy::doc_markdown)]
+
+use crate::connection::Timer;
+use crate::http;
+use crate::hub::{Hub, Subscription};
+use crate::protocol::Protocol;
+use crate::sink::{self, Source};
+use crate::stream;
+use anyhow::{anyhow, bail, Context, Result};
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tungstenite::handshake::server::Request;
+
+/// A stream a client can connect to us over.
+pub(crate) trait Stream: Read + Write + Send + 'static {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+    /// who's on the other end, for logging
+    fn peer(&self) -> String;
+}
+
+impl Stream for TcpStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+
+    fn peer(&self) -> String {
+        self.peer_addr()
+            .map_or_else(|_| "unknown address".into(), |addr| addr.to_string())
+    }
+}
+
+impl Stream for UnixStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        UnixStream::set_read_timeout(self, timeout)
+    }
+
+    fn peer(&self) -> String {
+        "Unix socket client".into()
+    }
+}
+
+/// A stream that gives back what was already read from it before reading any more, so that the
+/// WebSocket handshake can see the request we looked at to route it.
+pub(crate) struct Rewind<S> {
+    read: Vec<u8>,
+    position: usize,
+    stream: S,
+}
+
+impl<S> Rewind<S> {
+    pub(crate) fn new(read: Vec<u8>, stream: S) -> Self {
+        Self {
+            read,
+            position: 0,
+            stream,
+        }
+    }
+}
+
+impl<S: Read> Read for Rewind<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position < self.read.len() {
+            let len = (&self.read[self.position..]).read(buf)?;
+            self.position += len;
+            return Ok(len);
+        }
+        self.stream.read(buf)
+    }
+}
+
+impl<S: Write> Write for Rewind<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+impl<S: Stream> Stream for Rewind<S> {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.stream.set_read_timeout(timeout)
+    }
+
+    fn peer(&self) -> String {
+        self.stream.peer()
+    }
+}
+
+/// Listen on a Unix socket at `path` that only the current user can connect to, replacing any
+/// socket left behind by an earlier run.
+pub(crate) fn bind_unix(path: &Path) -> Result<UnixListener> {
+    if let Ok(metadata) = fs::symlink_metadata(path) {
+        if !metadata.file_type().is_socket() {
+            bail!("{} already exists, and isn't a socket", path.display());
+        }
+        if UnixStream::connect(path).is_ok() {
+            bail!(
+                "{} is in use; is vitellary already running?",
+                path.display()
+            );
+        }
+        fs::remove_file(path)
+            .with_context(|| format!("failed to remove old socket {}", path.display()))?;
+    }
+    // The socket is listening as soon as it's bound, so bind it in a directory only we can get
+    // into, and only move it into place once its permissions are set.
+    let directory = path.with_extension("sock.tmp");
+    let temporary = directory.join("socket");
+    let clean_up = || {
+        let _ = fs::remove_file(&temporary);
+        let _ = fs::remove_dir(&directory);
+    };
+    clean_up();
+    let bind = || -> Result<UnixListener> {
+        fs::DirBuilder::new().mode(0o700).create(&directory)?;
+        let listener = UnixListener::bind(&temporary)?;
+        fs::set_permissions(&temporary, fs::Permissions::from_mode(0o600))?;
+        fs::rename(&temporary, path)?;
+        Ok(listener)
+    };
+    let result = bind();
+    clean_up();
+    result.with_context(|| format!("failed to bind Unix socket {}", path.display()))
+}
+
+/// A socket that clients connect to us on.
+pub(crate) trait Listener: Send + 'static {
+    type Stream: Stream;
+
+    fn accept(&self) -> io::Result<Self::Stream>;
+}
+
+impl Listener for TcpListener {
+    type Stream = TcpStream;
+
+    fn accept(&self) -> io::Result<TcpStream> {
+        TcpListener::accept(self).map(|(stream, _)| stream)
+    }
+}
+
+impl Listener for UnixListener {
+    type Stream = UnixStream;
+
+    fn accept(&self) -> io::Result<UnixStream> {
+        UnixListener::accept(self).map(|(stream, _)| stream)
+    }
+}
+
+/// The WebSocket and HTTP server on one socket.
+pub(crate) struct Server<L> {
+    listener: L,
+    /// the protocol for clients that don't ask for one
+    protocol: Protocol,
+}
+
+impl<L> Server<L> {
+    pub(crate) fn new(listener: L, protocol: Protocol) -> Self {
+        Self { listener, protocol }
+    }
+}
+
+impl<L: Listener> Source for Server<L> {
+    /// Accept LiveSplit One connections, and make a sink for each of them. Plain HTTP requests go
+    /// to the HTTP API.
+    fn run(self: Box<Self>, hub: &Arc<Hub>) {
+        loop {
+            let mut stream = match self.listener.accept() {
+                Ok(stream) => stream,
+                Err(e) => {
+                    log::warn!("failed to accept connection: {}", e);
+                    continue;
+                }
+            };
+            let peer = stream.peer();
+            let hub = Arc::clone(hub);
+            let protocol = self.protocol;
+            std::thread::spawn(move || match http::read_request(&mut stream) {
+                Ok((request, read)) if request.websocket => {
+                    log::info!("client {} connected", peer);
+                    let stream = Rewind::new(read, stream);
+                    if let Err(e) = send_updates(stream, &peer, &hub.subscribe(), protocol) {
+                        log::info!("client {} disconnected: {}", peer, e);
+                    }
+                }
+                Ok((request, _)) => {
+                    if let Err(e) = http::handle(&request, stream, &hub) {
+                        log::debug!("HTTP request from {} failed: {:#}", peer, e);
+                    }
+                }
+                Err(e) => log::debug!("bad request from {}: {:#}", peer, e),
+            });
+        }
+    }
+}
+
+#[allow(clippy::result_large_err)] // the handshake callback's error type is up to tungstenite
+/// Send updates to a client until it disconnects: LiveSplit One commands, or the JSON stream if it
+/// connected to /stream.
+fn send_updates<S: Stream>(
+    stream: S,
+    peer: &str,
+    updates: &Subscription,
+    protocol: Protocol,
+) -> Result<()> {
+    let mut path = String::new();
+    let websocket = tungstenite::accept_hdr(stream, |request: &Request, response| {
+        path = request.uri().path().into();
+        Ok(response)
+    })
+    .map_err(|e| anyhow!("handshake failed: {}", e))?;
+    if path == "/stream" {
+        log::debug!("sending the JSON stream");
+        let mut client = stream::Client::new(peer.into(), Box::new(websocket));
+        return sink::run(&mut client, updates);
+    }
+    let protocol = Protocol::from_path(&path).unwrap_or(protocol);
+    log::debug!("using the {:?} protocol", protocol);
+    let mut timer = Timer::new(peer.into(), Box::new(websocket), protocol, updates);
+    sink::run(&mut timer, updates)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn binds_unix_sockets() {
+        let dir = env::temp_dir().join(format!("vitellary-server-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("vitellary.sock");
+
+        let listener = bind_unix(&path).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert!(!path.with_extension("sock.tmp").exists());
+        // another instance is listening there
+        assert!(bind_unix(&path).is_err());
+
+        // one that's gone leaves its socket behind, which we replace
+        drop(listener);
+        assert!(path.exists());
+        let _listener = bind_unix(&path).unwrap();
+        UnixStream::connect(&path).unwrap();
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,100 @@
+//! `vitellary revisions`: listing the revisions we know about, and looking them up.
+
+use crate::game::{self, Revision};
+use anyhow::Result;
+use argh::FromArgs;
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "revisions")]
+/// List the known revisions of VVVVVV, or show the layout of one of them.
+pub(crate) struct RevisionsCommand {
+    /// a tag (e.g. "2.3"), commit ID (which can be abbreviated), or output of `git describe`
+    #[argh(positional)]
+    name: Option<String>,
+}
+
+/// A run of consecutive commits on the master branch with the same layout.
+struct Range {
+    newest: &'static str,
+    oldest: &'static str,
+    commits: usize,
+    layout: usize,
+}
+
+impl Range {
+    fn print(&self) {
+        if self.commits == 1 {
+            println!("  {} (1 commit)", short(self.newest));
+        } else {
+            println!(
+                "  {}..{} ({} commits)",
+                short(self.oldest),
+                short(self.newest),
+                self.commits
+            );
+        }
+    }
+}
+
+fn short(id: &str) -> &str {
+    &id[..id.len().min(8)]
+}
+
+fn ranges() -> Vec<Range> {
+    let mut ranges: Vec<Range> = vec![];
+    for (id, layout) in game::history() {
+        match ranges.last_mut() {
+            Some(range) if range.layout == layout => {
+                range.oldest = id;
+                range.commits += 1;
+            }
+            _ => ranges.push(Range {
+                newest: id,
+                oldest: id,
+                commits: 1,
+                layout,
+            }),
+        }
+    }
+    ranges
+}
+
+impl RevisionsCommand {
+    pub(crate) fn run(&self) -> Result<()> {
+        let ranges = ranges();
+        let Some(name) = &self.name else {
+            println!("tags:");
+            for (tag, layout) in game::tags() {
+                println!("  {} (layout {})", tag, layout);
+            }
+            println!("commits, newest first:");
+            for range in &ranges {
+                print!("  layout {:<3}", range.layout);
+                range.print();
+            }
+            return Ok(());
+        };
+
+        let resolved = Revision::resolve(name)?;
+        if let Some(commit) = resolved.commit {
+            println!("commit {}", commit);
+        }
+        println!("layout {}", resolved.layout);
+        println!("{}", resolved.revision);
+        let tags = game::tags()
+            .filter(|(_, layout)| *layout == resolved.layout)
+            .map(|(tag, _)| tag)
+            .collect::<Vec<_>>();
+        if !tags.is_empty() {
+            println!("tags with this layout: {}", tags.join(", "));
+        }
+        println!("commits with this layout, newest first:");
+        for range in ranges
+            .iter()
+            .filter(|range| range.layout == resolved.layout)
+        {
+            range.print();
+        }
+        Ok(())
+    }
+}
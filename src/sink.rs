@@ -0,0 +1,98 @@
+//! Things that do something with every update by themselves, like recording splits or writing a
+//! log. Each sink gets its own thread and its own queue of updates, so a slow or broken one can't
+//! hold up splitting for the others.
+//!
+//! Timers are sinks too, one for each connection, whether LiveSplit One connects to us or we
+//! connect to LiveSplit's server. Those come and go, so they're made by a [`Source`] as they
+//! connect, and each gets its own queue then, so that it's sent the run so far.
+
+#![allow(clippy::doc_markdown)]
+
+use crate::game::Update;
+use crate::hub::{Hub, Subscription};
+use anyhow::Result;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// how long sinks wait for an update before they're called idle, unless they say otherwise
+const IDLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Something that wants every update from the game.
+pub(crate) trait Sink: Send {
+    /// what to call it in the logs
+    fn name(&self) -> String;
+
+    /// Handle an update. Returning an error stops this sink, and only this sink.
+    fn update(&mut self, update: &Update) -> Result<()>;
+
+    /// Called when there haven't been any updates for a while (e.g. while we're not attached), for
+    /// sinks that hold things back and need a chance to send them.
+    fn idle(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// How long to wait for an update before calling [`Sink::idle`].
+    fn idle_interval(&self) -> Duration {
+        IDLE_INTERVAL
+    }
+
+    /// Whether to take updates yet. Until then, they stay queued and only [`Sink::idle`] is called.
+    fn is_ready(&self) -> bool {
+        true
+    }
+}
+
+/// Something that makes sinks as timers connect: a server makes one for each client, and
+/// `--connect` makes one each time it connects.
+pub(crate) trait Source: Send {
+    /// Keep making sinks, and feeding each one with [`run`], for as long as vitellary runs.
+    fn run(self: Box<Self>, hub: &Arc<Hub>);
+}
+
+/// Feed `sink` with `updates` until it fails.
+pub(crate) fn run(sink: &mut dyn Sink, updates: &Subscription) -> Result<()> {
+    loop {
+        let update = if sink.is_ready() {
+            updates.recv_timeout(sink.idle_interval())
+        } else {
+            None
+        };
+        match update {
+            Some(update) => sink.update(&update)?,
+            None => sink.idle()?,
+        }
+    }
+}
+
+/// The sinks and sources enabled on the command line.
+#[derive(Default)]
+pub(crate) struct Registry {
+    sinks: Vec<Box<dyn Sink>>,
+    sources: Vec<Box<dyn Source>>,
+}
+
+impl Registry {
+    pub(crate) fn add(&mut self, sink: impl Sink + 'static) {
+        self.sinks.push(Box::new(sink));
+    }
+
+    pub(crate) fn add_source(&mut self, source: impl Source + 'static) {
+        self.sources.push(Box::new(source));
+    }
+
+    /// Start a thread for each sink and each source, driven by updates from `hub`.
+    pub(crate) fn spawn(self, hub: &Arc<Hub>) {
+        for mut sink in self.sinks {
+            let updates = hub.subscribe();
+            std::thread::spawn(move || {
+                if let Err(e) = run(&mut *sink, &updates) {
+                    log::error!("{} failed, and has stopped: {:#}", sink.name(), e);
+                }
+            });
+        }
+        for source in self.sources {
+            let hub = Arc::clone(hub);
+            std::thread::spawn(move || source.run(&hub));
+        }
+    }
+}
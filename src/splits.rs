@@ -0,0 +1,98 @@
+//! A timer of our own, so that runs get recorded even when no LiveSplit One is connected.
+
+#![allow(clippy::doc_markdown)]
+
+use crate::game::{Event, Update};
+use crate::sink::Sink;
+use anyhow::{anyhow, Context, Result};
+use livesplit_core::run::parser::livesplit;
+use livesplit_core::run::saver::livesplit::save_timer;
+use livesplit_core::{TimeSpan, Timer, TimerPhase, TimingMethod};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A livesplit-core timer, and the splits file it was loaded from.
+pub(crate) struct Splits {
+    timer: Timer,
+    path: PathBuf,
+}
+
+impl Splits {
+    /// Load a LiveSplit splits file (`.lss`).
+    pub(crate) fn load(path: &Path) -> Result<Self> {
+        let load = || -> Result<Timer> {
+            let run = livesplit::parse(&fs::read_to_string(path)?)?;
+            let mut timer = Timer::new(run).map_err(|_| anyhow!("there are no segments"))?;
+            timer.set_current_timing_method(TimingMethod::GameTime);
+            Ok(timer)
+        };
+        let timer =
+            load().with_context(|| format!("failed to load splits from {}", path.display()))?;
+        log::info!(
+            "loaded {} splits for {}",
+            timer.run().len(),
+            timer.run().extended_name(false)
+        );
+        Ok(Self {
+            timer,
+            path: path.into(),
+        })
+    }
+
+    /// Save the splits back to the file they came from, including the attempt in progress.
+    fn save(&self) {
+        let save = || -> Result<()> {
+            let mut lss = String::new();
+            save_timer(&self.timer, &mut lss)?;
+            // so that a crash can't leave the splits half-written
+            let temporary = self.path.with_extension("lss.tmp");
+            fs::write(&temporary, lss)?;
+            fs::rename(&temporary, &self.path)?;
+            Ok(())
+        };
+        match save() {
+            Ok(()) => log::info!("saved splits to {}", self.path.display()),
+            Err(e) => log::error!("failed to save splits to {}: {:#}", self.path.display(), e),
+        }
+    }
+}
+
+impl Sink for Splits {
+    fn name(&self) -> String {
+        format!("splits in {}", self.path.display())
+    }
+
+    fn update(&mut self, update: &Update) -> Result<()> {
+        let time = TimeSpan::from_seconds(update.time.as_secs_f64());
+        match update.event {
+            None => self.timer.set_game_time(time),
+            Some(Event::NewGame) => {
+                self.timer.start();
+                self.timer.initialize_game_time();
+                // the game keeps its own time, so it should only change when we set it
+                self.timer.pause_game_time();
+                self.timer.set_game_time(time);
+            }
+            Some(Event::Reset) => {
+                if self.timer.current_phase() != TimerPhase::NotRunning {
+                    self.timer.reset(true);
+                    self.save();
+                }
+            }
+            // game time is always paused
+            Some(Event::Detached | Event::Attached) => {}
+            // everything else is a split
+            Some(_) => {
+                self.timer.set_game_time(time);
+                if let Some(segment) = self.timer.current_split() {
+                    log::info!("split {} at {:?}", segment.name(), update.time);
+                }
+                self.timer.split();
+                if self.timer.current_phase() == TimerPhase::Ended {
+                    self.save();
+                }
+            }
+        }
+        Ok(())
+    }
+}
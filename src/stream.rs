@@ -0,0 +1,119 @@
+//! A stream of everything the splitter sees, as JSON, for overlays and other tools.
+//!
+//! Each message looks like
+//! `{"version":1,"timestamp":1700000000.25,"time":12.5,"event":"verdigris","state":{"room":[115,100],"gamestate":0,"state":3006,"deaths":2},"previous":{...}}`,
+//! where `timestamp` is the wall-clock time in seconds since the Unix epoch, `time` is the game
+//! time, `event` is null if nothing happened, and `previous` is only there if the state changed.
+
+use crate::connection::{Connection, POLL_INTERVAL};
+use crate::game::{Event, State, Update};
+use crate::sink::Sink;
+use anyhow::Result;
+use serde::Serialize;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// the version of the message format, which changes whenever a change could break clients
+const VERSION: u32 = 1;
+
+#[derive(Serialize)]
+struct Message {
+    version: u32,
+    timestamp: f64,
+    /// game time, in seconds
+    time: f64,
+    event: Option<Event>,
+    state: State,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    previous: Option<State>,
+}
+
+/// Encode a message for `update`. `previous` is the state in the last message, if it changed.
+pub(crate) fn encode(update: &Update, previous: Option<State>) -> String {
+    serde_json::to_string(&Message {
+        version: VERSION,
+        timestamp: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs_f64(),
+        time: update.time.as_secs_f64(),
+        event: update.event,
+        state: update.state,
+        previous,
+    })
+    .expect("messages always serialize")
+}
+
+/// Decides which updates are worth a message.
+pub(crate) struct Encoder {
+    /// whether the game time changing is worth a message by itself
+    time_changes: bool,
+    /// the last update we encoded
+    sent: Option<Update>,
+}
+
+impl Encoder {
+    pub(crate) fn new(time_changes: bool) -> Self {
+        Self {
+            time_changes,
+            sent: None,
+        }
+    }
+
+    /// A message for `update`, unless nothing worth mentioning has changed.
+    pub(crate) fn encode(&mut self, update: &Update) -> Option<String> {
+        let previous = self
+            .sent
+            .map(|sent| sent.state)
+            .filter(|state| *state != update.state);
+        let time_changed = self.sent.is_none_or(|sent| sent.time != update.time);
+        if update.event.is_none()
+            && previous.is_none()
+            && self.sent.is_some()
+            && !(self.time_changes && time_changed)
+        {
+            return None;
+        }
+        self.sent = Some(*update);
+        Some(encode(update, previous))
+    }
+}
+
+/// A client of the stream, sent updates until the connection fails.
+pub(crate) struct Client {
+    /// who's on the other end, for logging
+    peer: String,
+    connection: Box<dyn Connection + Send>,
+    encoder: Encoder,
+}
+
+impl Client {
+    pub(crate) fn new(peer: String, connection: Box<dyn Connection + Send>) -> Self {
+        Self {
+            peer,
+            connection,
+            encoder: Encoder::new(true),
+        }
+    }
+}
+
+impl Sink for Client {
+    fn name(&self) -> String {
+        format!("stream client {}", self.peer)
+    }
+
+    fn update(&mut self, update: &Update) -> Result<()> {
+        match self.encoder.encode(update) {
+            Some(message) => self.connection.send(message),
+            None => Ok(()),
+        }
+    }
+
+    fn idle(&mut self) -> Result<()> {
+        // nothing to do with messages from the client, but this notices when it disconnects
+        self.connection.receive(POLL_INTERVAL).map(drop)
+    }
+
+    fn idle_interval(&self) -> Duration {
+        POLL_INTERVAL
+    }
+}
@@ -0,0 +1,101 @@
+//! A directory of small text files for streaming software to show, e.g. as OBS text sources:
+//!
+//! - `igt.txt`: the game time
+//! - `room.txt`: the room the player is in
+//! - `last_split.txt`: the last split in the current run
+//! - `deaths.txt`: how many times the player has died this run, if we know where the game keeps
+//!   that
+//! - `attempts.txt`: how many runs have been started, counting from whatever was in the file
+
+use crate::game::{format_time, Event, Update};
+use crate::sink::Sink;
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+/// the least time between writes when only the game time or state has changed
+const WRITE_INTERVAL: Duration = Duration::from_millis(250);
+
+pub(crate) struct TextFiles {
+    dir: PathBuf,
+    attempts: u64,
+    last_split: Option<Event>,
+    /// what's in each file, so that we only rewrite the ones that change
+    written: HashMap<&'static str, String>,
+    last_write: Option<Instant>,
+}
+
+impl TextFiles {
+    /// Start keeping files in `dir`, creating it if need be.
+    pub(crate) fn open(dir: PathBuf) -> Result<Self> {
+        fs::create_dir_all(&dir).with_context(|| format!("failed to create {}", dir.display()))?;
+        let attempts = fs::read_to_string(dir.join("attempts.txt"))
+            .ok()
+            .and_then(|attempts| attempts.trim().parse().ok())
+            .unwrap_or(0);
+        Ok(Self {
+            dir,
+            attempts,
+            last_split: None,
+            written: HashMap::new(),
+            last_write: None,
+        })
+    }
+
+    /// Replace a file's contents, unless they haven't changed.
+    fn write(&mut self, name: &'static str, contents: String) -> Result<()> {
+        if self.written.get(name) == Some(&contents) {
+            return Ok(());
+        }
+        let path = self.dir.join(name);
+        // so that nothing ever sees a half-written file
+        let temporary = path.with_extension("txt.tmp");
+        fs::write(&temporary, &contents)
+            .and_then(|()| fs::rename(&temporary, &path))
+            .with_context(|| format!("failed to write {}", path.display()))?;
+        self.written.insert(name, contents);
+        Ok(())
+    }
+}
+
+impl Sink for TextFiles {
+    fn name(&self) -> String {
+        format!("text files in {}", self.dir.display())
+    }
+
+    fn update(&mut self, update: &Update) -> Result<()> {
+        match update.event {
+            Some(Event::NewGame) => {
+                self.attempts += 1;
+                self.last_split = None;
+            }
+            Some(Event::Reset) => self.last_split = None,
+            Some(event) if event.is_split() => self.last_split = Some(event),
+            _ => {}
+        }
+        if update.event.is_none()
+            && self
+                .last_write
+                .is_some_and(|last| last.elapsed() < WRITE_INTERVAL)
+        {
+            return Ok(());
+        }
+        self.last_write = Some(Instant::now());
+        let (x, y) = update.state.room;
+        self.write("igt.txt", format_time(update.time))?;
+        self.write("room.txt", format!("{},{}", x, y))?;
+        self.write(
+            "last_split.txt",
+            self.last_split
+                .map(|split| split.to_string())
+                .unwrap_or_default(),
+        )?;
+        if let Some(deaths) = update.state.deaths {
+            self.write("deaths.txt", deaths.to_string())?;
+        }
+        self.write("attempts.txt", self.attempts.to_string())?;
+        Ok(())
+    }
+}
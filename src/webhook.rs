@@ -0,0 +1,237 @@
+//! Sending events to HTTP URLs as they happen, for dashboards and the like.
+//!
+//! The body of each request is the same JSON as a `/stream` message. Each URL gets its own queue,
+//! so events arrive in order, and a slow or broken receiver only holds up its own.
+
+use crate::game::Update;
+use crate::sink::Sink;
+use crate::stream;
+use anyhow::{anyhow, bail, Context, Result};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// how long to wait for the receiver to connect, read the request or respond
+const TIMEOUT: Duration = Duration::from_secs(5);
+/// how many times to try delivering each event
+const ATTEMPTS: u32 = 3;
+/// how long to wait before the first retry, by default
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// An `http://` URL to POST events to.
+#[derive(Debug, Clone)]
+pub(crate) struct Webhook {
+    url: String,
+    /// `host:port`, for connecting and for the `Host` header
+    authority: String,
+    path: String,
+    /// how long to wait before the first retry; this doubles after each one
+    retry_delay: Duration,
+}
+
+impl FromStr for Webhook {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some(rest) = s.strip_prefix("http://") else {
+            return Err(if s.starts_with("https://") {
+                format!("{s}: https isn't supported")
+            } else {
+                format!("{s}: expected an http:// URL")
+            });
+        };
+        let (authority, path) = rest.find('/').map_or((rest, "/"), |i| rest.split_at(i));
+        if authority.is_empty() {
+            return Err(format!("{s}: no host"));
+        }
+        let authority = if authority
+            .rsplit_once(':')
+            .is_some_and(|(_, port)| !port.contains(']'))
+        {
+            authority.to_owned()
+        } else {
+            format!("{authority}:80")
+        };
+        Ok(Self {
+            url: s.into(),
+            authority,
+            path: path.into(),
+            retry_delay: RETRY_DELAY,
+        })
+    }
+}
+
+impl Webhook {
+    /// Make one attempt at delivering `body`.
+    fn post(&self, body: &str) -> Result<()> {
+        let address = self
+            .authority
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| anyhow!("{} didn't resolve to anything", self.authority))?;
+        let mut stream = TcpStream::connect_timeout(&address, TIMEOUT)?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        write!(
+            stream,
+            "POST {} HTTP/1.1\r\n\
+             Host: {}\r\n\
+             User-Agent: vitellary/{}\r\n\
+             Content-Type: application/json\r\n\
+             Content-Length: {}\r\n\
+             Connection: close\r\n\
+             \r\n\
+             {}",
+            self.path,
+            self.authority,
+            env!("CARGO_PKG_VERSION"),
+            body.len(),
+            body
+        )?;
+        let mut status = String::new();
+        BufReader::new(stream)
+            .read_line(&mut status)
+            .context("no response")?;
+        let code = status.split_whitespace().nth(1).unwrap_or_default();
+        if !code.starts_with('2') {
+            bail!("got {:?}", status.trim_end());
+        }
+        Ok(())
+    }
+}
+
+impl Sink for Webhook {
+    fn name(&self) -> String {
+        format!("webhook {}", self.url)
+    }
+
+    fn update(&mut self, update: &Update) -> Result<()> {
+        let Some(event) = update.event else {
+            return Ok(());
+        };
+        let body = stream::encode(update, None);
+        let mut delay = self.retry_delay;
+        for attempt in 1..=ATTEMPTS {
+            match self.post(&body) {
+                Ok(()) => {
+                    log::debug!("sent {:?} to {}", event, self.url);
+                    return Ok(());
+                }
+                Err(e) if attempt < ATTEMPTS => {
+                    log::debug!(
+                        "failed to send {:?} to {}: {:#}; retrying",
+                        event,
+                        self.url,
+                        e
+                    );
+                    std::thread::sleep(delay);
+                    delay *= 2;
+                }
+                // give up on this event, but not the ones after it
+                Err(e) => log::warn!("failed to send {:?} to {}: {:#}", event, self.url, e),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::Event;
+    use serde_json::Value;
+    use std::io::Read;
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+    use std::thread;
+
+    #[test]
+    fn parses_urls() {
+        let authority_and_path = |url: &str| {
+            let webhook = url.parse::<Webhook>().unwrap();
+            (webhook.authority, webhook.path)
+        };
+        let pair = |authority: &str, path: &str| (authority.to_owned(), path.to_owned());
+        assert_eq!(
+            authority_and_path("http://127.0.0.1:8080/hook?x=1"),
+            pair("127.0.0.1:8080", "/hook?x=1")
+        );
+        assert_eq!(
+            authority_and_path("http://example.com"),
+            pair("example.com:80", "/")
+        );
+        assert_eq!(
+            authority_and_path("http://[::1]:8080/hook"),
+            pair("[::1]:8080", "/hook")
+        );
+        assert_eq!(authority_and_path("http://[::1]/"), pair("[::1]:80", "/"));
+        assert!("https://example.com/".parse::<Webhook>().is_err());
+        assert!("example.com/".parse::<Webhook>().is_err());
+        assert!("http:///hook".parse::<Webhook>().is_err());
+    }
+
+    /// A stand-in receiver, which answers each request with the next of `statuses` and records
+    /// the event it was for.
+    fn receiver(listener: &TcpListener, statuses: &[u16], events: &mpsc::Sender<String>) {
+        for status in statuses {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(&stream);
+            let mut length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line == "\r\n" {
+                    break;
+                }
+                if let Some(value) = line.to_ascii_lowercase().strip_prefix("content-length:") {
+                    length = value.trim().parse().unwrap();
+                }
+            }
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+            let body: Value = serde_json::from_slice(&body).unwrap();
+            write!(
+                &stream,
+                "HTTP/1.1 {status} Whatever\r\nContent-Length: 0\r\n\r\n"
+            )
+            .unwrap();
+            events.send(body["event"].as_str().unwrap().into()).unwrap();
+        }
+    }
+
+    #[test]
+    fn retries_and_gives_up() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let statuses = [200, 503, 200, 500, 500, 500, 204];
+        let (sender, events) = mpsc::channel();
+        thread::spawn(move || receiver(&listener, &statuses, &sender));
+
+        let mut webhook = url.parse::<Webhook>().unwrap();
+        webhook.retry_delay = Duration::from_millis(10);
+        let update = |event| Update::test(Duration::from_secs(12), (115, 100), 0, event);
+        for event in [
+            Some(Event::NewGame),
+            None,
+            Some(Event::Verdigris),
+            Some(Event::Violet),
+            Some(Event::Reset),
+        ] {
+            webhook.update(&update(event)).unwrap();
+        }
+        let received = events.iter().take(statuses.len()).collect::<Vec<_>>();
+        assert_eq!(
+            received,
+            [
+                "new_game",
+                "verdigris",
+                "verdigris",
+                "violet",
+                "violet",
+                "violet",
+                "reset"
+            ]
+        );
+    }
+}